@@ -1,52 +1,177 @@
-use crate::player::Player;
+use crate::camera::Camera;
+use crate::chat::{Chat, ChatPanel};
+use crate::connection::{Connection, ConnectionStatus, Profile};
+use crate::emote::{EmoteOverlay, Emotes};
+use crate::interpolation::Interpolation;
+use crate::minimap::Minimap;
+use crate::player::{NameTag, Player, PlayerId, PlayerSquare, SpeechBubble};
+use crate::prediction::Prediction;
+use crate::terrain::Terrain;
 use crossterm::event::{KeyCode, KeyEventKind, KeyModifiers};
-use ratatui::{DefaultTerminal, Frame, prelude::Buffer, prelude::Rect, widgets::Widget};
-use std::{env, io, net::UdpSocket, sync::mpsc};
+use ratatui::{
+    DefaultTerminal, Frame,
+    prelude::Buffer,
+    prelude::Rect,
+    style::{Color, Stylize},
+    text::Line,
+    widgets::Widget,
+};
+use roam_protocol::{
+    ChatMessage, ChatRequest, ClientMessage, Direction, Emote, Input, MAX_DATAGRAM_SIZE, Map,
+    PlayerColor, PlayerEmote, Presence,
+};
+use std::{
+    env, io,
+    net::UdpSocket,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 pub enum Event {
     Input(crossterm::event::KeyEvent),
-    SetPlayers(Vec<Player>),
-    OwnPosition(Player),
+    /// Our player, the map of the room it is in, the room's name and how
+    /// many ticks a second the server runs.
+    Welcome(Player, Map, String, u32),
+    /// Everyone the server can see, including us, the last of our inputs it
+    /// has applied and the tick it saw them on.
+    SetPlayers(Vec<Player>, u32, u32),
+    /// A player left the game. Those who only go out of view drop out of the
+    /// next snapshot instead.
+    PlayerLeft(PlayerId),
+    /// The server refused the input with this sequence number.
+    MoveRejected(u32),
+    Chat(ChatMessage),
+    Emote(PlayerEmote),
+    /// The server refused something we sent.
+    ServerError(String),
+    ConnectionStatus(ConnectionStatus),
+    /// Redraw, so remote players keep moving between snapshots.
+    Render,
+    Move(Input),
+    SendChat(ChatRequest),
+    SendEmote(Emote),
+    SetPresence(Presence),
+    Disconnect,
 }
 
+/// Something a key press asks the server to do.
+enum Command {
+    Move(Direction),
+    Emote(Emote),
+    SetPresence(Presence),
+}
+
+/// How often the screen is redrawn while nothing else happens.
+pub const RENDER_INTERVAL: Duration = Duration::from_millis(33);
+
 #[derive(Clone)]
 pub struct App {
     pub exit: bool,
-    pub players: Vec<Player>,
     pub own_player: Player,
+    pub map: Map,
+    pub room: String,
+    pub status: ConnectionStatus,
+    pub show_minimap: bool,
+    chat: Chat,
+    emotes: Emotes,
+    prediction: Prediction,
+    interpolation: Interpolation,
 }
 
-pub fn run_background_connection(tx: mpsc::Sender<Event>, own_rx: mpsc::Receiver<Event>) {
+pub fn run_background_connection(
+    tx: mpsc::Sender<Event>,
+    own_rx: mpsc::Receiver<Event>,
+    profile: Profile,
+) {
     let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    // The server only sends snapshots when something changed, so a blocking
+    // recv would hold back our own movement.
+    socket
+        .set_read_timeout(Some(Duration::from_millis(10)))
+        .unwrap();
     let server_addr = env::var("SERVER_ADDR").unwrap();
-    if let Err(e) = socket.send_to(b"CONNECT", &server_addr) {
-        eprintln!("Failed to connect to server: {}", e);
+    let mut connection = Connection::new(profile);
+    // A connected socket only hands us datagrams from the server, so nobody
+    // else can feed us snapshots.
+    let sent = socket
+        .connect(&server_addr)
+        .and_then(|()| socket.send(&connection.connect()));
+    if let Err(e) = sent {
+        // Printing would garble the screen, so it goes in the chat panel.
+        let _ = tx.send(Event::ServerError(format!(
+            "Failed to connect to server: {e}"
+        )));
         return;
     }
 
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
     loop {
-        let mut buf = [0; 1024];
-        if let Ok((size, _)) = socket.recv_from(&mut buf)
-            && let Ok(msg) = std::str::from_utf8(&buf[..size])
-            && msg.starts_with("PLAYERS")
-        {
-            let json_start = "PLAYERS".len();
-            if let Some(json_str) = msg.get(json_start..).and_then(|s| s.strip_suffix('\n'))
-                && let Ok(players) = serde_json::from_str::<Vec<Player>>(json_str)
-            {
-                let _ = tx.send(Event::SetPlayers(players));
-            }
-        }
-        // Handle own position updates
-        if let Ok(Event::OwnPosition(player)) = own_rx.try_recv() {
-            let json = serde_json::to_string(&player).unwrap();
-            let _ = socket.send_to(json.as_bytes(), &server_addr);
+        if let Ok(size) = socket.recv(&mut buf) {
+            connection.handle_datagram(&buf[..size]);
+        }
+        connection.poll();
+        for event in connection.take_events() {
+            let _ = tx.send(event);
+        }
+        // Handle own movement
+        let leaving = match own_rx.try_recv() {
+            Ok(Event::Move(input)) => {
+                connection.send(ClientMessage::Move(input));
+                false
+            }
+            Ok(Event::SendChat(request)) => {
+                connection.send(ClientMessage::Chat(request));
+                false
+            }
+            Ok(Event::SendEmote(emote)) => {
+                connection.send(ClientMessage::Emote(emote));
+                false
+            }
+            Ok(Event::SetPresence(presence)) => {
+                connection.send(ClientMessage::SetPresence(presence));
+                false
+            }
+            // The app going away without saying goodbye counts as leaving.
+            Ok(Event::Disconnect) | Err(mpsc::TryRecvError::Disconnected) => {
+                connection.send(ClientMessage::Disconnect);
+                true
+            }
+            _ => false,
+        };
+        for datagram in connection.take_outgoing() {
+            let _ = socket.send(&datagram);
+        }
+        if leaving {
+            return;
         }
     }
 }
 
 impl App {
+    pub fn new() -> Self {
+        Self {
+            exit: false,
+            own_player: Player {
+                id: PlayerId::default(),
+                name: String::new(),
+                color: PlayerColor::default(),
+                glyph: None,
+                presence: Presence::default(),
+                x: 0,
+                y: 0,
+            },
+            map: Map::default(),
+            room: String::new(),
+            status: ConnectionStatus::Connecting,
+            show_minimap: false,
+            chat: Chat::default(),
+            emotes: Emotes::default(),
+            prediction: Prediction::default(),
+            interpolation: Interpolation::default(),
+        }
+    }
+
     pub fn run(
         &mut self,
         terminal: &mut DefaultTerminal,
@@ -54,24 +179,91 @@ impl App {
         tx: mpsc::Sender<Event>,
     ) -> io::Result<()> {
         while !self.exit {
-            match rx.recv().unwrap() {
-                Event::Input(key_event) => {
-                    self.handle_key_event(key_event)?;
-                    let _ = tx.send(Event::OwnPosition(self.own_player.clone()));
-                }
-                Event::SetPlayers(players) => self.players = players,
-                _ => {}
+            if let Some(outgoing) = self.update(rx.recv().unwrap())? {
+                let _ = tx.send(outgoing);
             }
             terminal.draw(|frame| self.draw(frame))?;
         }
         Ok(())
     }
 
+    /// Applies an event to the app state, returning anything that should be
+    /// passed on to the background connection.
+    fn update(&mut self, event: Event) -> io::Result<Option<Event>> {
+        match event {
+            Event::Input(key_event) => {
+                if self.chat.is_typing() {
+                    let request = self.handle_chat_key(key_event);
+                    if self.exit {
+                        return Ok(Some(Event::Disconnect));
+                    }
+                    return Ok(request.map(Event::SendChat));
+                }
+                let command = self.handle_key_event(key_event)?;
+                if self.exit {
+                    return Ok(Some(Event::Disconnect));
+                }
+                // Moving while there is no server to confirm it would only
+                // pile up inputs to throw away on the next WELCOME.
+                if self.status != ConnectionStatus::Connected {
+                    return Ok(None);
+                }
+                return Ok(match command {
+                    Some(Command::Move(direction)) => self
+                        .prediction
+                        .apply(&mut self.own_player, direction, &self.map)
+                        .map(Event::Move),
+                    Some(Command::Emote(emote)) => Some(Event::SendEmote(emote)),
+                    Some(Command::SetPresence(presence)) => Some(Event::SetPresence(presence)),
+                    None => None,
+                });
+            }
+            Event::Welcome(player, map, room, tick_rate) => {
+                self.own_player = player;
+                self.map = map;
+                self.room = room;
+                self.prediction.reset();
+                self.interpolation.reset(tick_rate);
+            }
+            Event::SetPlayers(players, processed, tick) => {
+                // The server owns our position; it arrives with everyone else's.
+                let (own, others) = players
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|player| player.id == self.own_player.id);
+                if let Some(own) = own.into_iter().next() {
+                    self.own_player = self.prediction.reconcile(own, processed, &self.map);
+                }
+                self.interpolation.push(tick, Instant::now(), others);
+            }
+            Event::MoveRejected(sequence) => {
+                if let Some(own) = self.prediction.reject(sequence, &self.map) {
+                    self.own_player = own;
+                }
+            }
+            Event::PlayerLeft(id) => {
+                self.interpolation.remove(id);
+                self.emotes.remove(id);
+            }
+            Event::Emote(emote) => self.emotes.play(emote, Instant::now()),
+            Event::Chat(message) => self.chat.push(message),
+            Event::ServerError(reason) => self.chat.notice(reason),
+            Event::ConnectionStatus(status) => self.status = status,
+            _ => {}
+        }
+        Ok(None)
+    }
+
     pub fn draw(&self, frame: &mut Frame) {
         frame.render_widget(self, frame.area());
     }
 
-    fn handle_key_event(&mut self, key_event: crossterm::event::KeyEvent) -> io::Result<()> {
+    /// Handles a key press, returning what to ask the server to do, if
+    /// anything.
+    fn handle_key_event(
+        &mut self,
+        key_event: crossterm::event::KeyEvent,
+    ) -> io::Result<Option<Command>> {
+        let mut command = None;
         if key_event.kind == KeyEventKind::Press {
             match key_event.code {
                 KeyCode::Char('q') => {
@@ -83,35 +275,68 @@ impl App {
                 KeyCode::Esc => {
                     self.exit = true;
                 }
+                KeyCode::Char('m') => {
+                    self.show_minimap = !self.show_minimap;
+                }
+                KeyCode::Enter => self.chat.start_typing(),
+                KeyCode::PageUp => self.chat.scroll_up(),
+                KeyCode::PageDown => self.chat.scroll_down(),
                 KeyCode::Char('w') | KeyCode::Up => {
-                    self.own_player.y = self.own_player.y.saturating_sub(1);
+                    command = Some(Command::Move(Direction::Up));
                 }
                 KeyCode::Char('a') | KeyCode::Left => {
-                    self.own_player.x = self.own_player.x.saturating_sub(2);
+                    command = Some(Command::Move(Direction::Left));
                 }
                 KeyCode::Char('s') | KeyCode::Down => {
-                    self.own_player.y = self.own_player.y.saturating_add(1);
+                    command = Some(Command::Move(Direction::Down));
                 }
                 KeyCode::Char('d') | KeyCode::Right => {
-                    self.own_player.x = self.own_player.x.saturating_add(2);
+                    command = Some(Command::Move(Direction::Right));
+                }
+                KeyCode::Char('1') => command = Some(Command::Emote(Emote::Wave)),
+                KeyCode::Char('2') => command = Some(Command::Emote(Emote::Dance)),
+                KeyCode::Char('3') => command = Some(Command::Emote(Emote::Exclaim)),
+                KeyCode::Char('4') => command = Some(Command::Emote(Emote::Question)),
+                KeyCode::Char('z') => {
+                    let next = match self.own_player.presence {
+                        Presence::Available => Presence::Away,
+                        Presence::Away => Presence::Busy,
+                        Presence::Busy => Presence::Available,
+                    };
+                    command = Some(Command::SetPresence(next));
                 }
                 _ => {}
             };
         }
 
-        Ok(())
+        Ok(command)
+    }
+
+    /// Handles a key press while typing a chat message, returning the
+    /// message once it is sent.
+    fn handle_chat_key(&mut self, key_event: crossterm::event::KeyEvent) -> Option<ChatRequest> {
+        if key_event.kind != KeyEventKind::Press {
+            return None;
+        }
+        match key_event.code {
+            KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.exit = true;
+            }
+            KeyCode::Enter => return self.chat.take_draft(),
+            KeyCode::Esc => self.chat.cancel_typing(),
+            KeyCode::Tab => self.chat.toggle_scope(),
+            KeyCode::Backspace => self.chat.backspace(),
+            KeyCode::Char(c) => self.chat.type_char(c),
+            _ => {}
+        }
+        None
     }
 }
 
 impl App {
     pub fn handle_event(&mut self, event: Event, tx: &UnboundedSender<Event>) {
-        match event {
-            Event::Input(key_event) => {
-                let _ = self.handle_key_event(key_event);
-                let _ = tx.send(Event::OwnPosition(self.own_player.clone()));
-            }
-            Event::SetPlayers(players) => self.players = players,
-            _ => {}
+        if let Ok(Some(outgoing)) = self.update(event) {
+            let _ = tx.send(outgoing);
         }
     }
 }
@@ -119,45 +344,141 @@ impl App {
 pub async fn run_background_connection_async(
     tx: UnboundedSender<Event>,
     mut own_rx: UnboundedReceiver<Event>,
+    profile: Profile,
 ) {
     let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await.unwrap();
     let server_addr = env::var("SERVER_ADDR").unwrap();
-    if let Err(e) = socket.send_to(b"CONNECT", &server_addr).await {
-        eprintln!("Failed to connect to server: {}", e);
+    let mut connection = Connection::new(profile);
+    // A connected socket only hands us datagrams from the server, so nobody
+    // else can feed us snapshots.
+    let sent = match socket.connect(&server_addr).await {
+        Ok(()) => socket.send(&connection.connect()).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = sent {
+        // Printing would garble the screen, so it goes in the chat panel.
+        let _ = tx.send(Event::ServerError(format!(
+            "Failed to connect to server: {e}"
+        )));
         return;
     }
 
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let mut poll_interval = tokio::time::interval(Duration::from_millis(100));
     loop {
-        let mut buf = [0; 1024];
+        let mut leaving = false;
         tokio::select! {
-            result = socket.recv_from(&mut buf) => {
-                if let Ok((size, _)) = result
-                    && let Ok(msg) = std::str::from_utf8(&buf[..size])
-                    && msg.starts_with("PLAYERS")
-                {
-                    let json_start = "PLAYERS".len();
-                    if let Some(json_str) = msg.get(json_start..).and_then(|s| s.strip_suffix('\n'))
-                        && let Ok(players) = serde_json::from_str::<Vec<Player>>(json_str)
-                    {
-                        let _ = tx.send(Event::SetPlayers(players));
-                    }
+            result = socket.recv(&mut buf) => {
+                if let Ok(size) = result {
+                    connection.handle_datagram(&buf[..size]);
                 }
             }
+            _ = poll_interval.tick() => connection.poll(),
             event = own_rx.recv() => {
-                if let Some(Event::OwnPosition(player)) = event {
-                    let json = serde_json::to_string(&player).unwrap();
-                    let _ = socket.send_to(json.as_bytes(), &server_addr).await;
+                match event {
+                    Some(Event::Move(input)) => {
+                        connection.send(ClientMessage::Move(input));
+                    }
+                    Some(Event::SendChat(request)) => {
+                        connection.send(ClientMessage::Chat(request));
+                    }
+                    Some(Event::SendEmote(emote)) => {
+                        connection.send(ClientMessage::Emote(emote));
+                    }
+                    Some(Event::SetPresence(presence)) => {
+                        connection.send(ClientMessage::SetPresence(presence));
+                    }
+                    // The app going away without saying goodbye counts as leaving.
+                    Some(Event::Disconnect) | None => {
+                        connection.send(ClientMessage::Disconnect);
+                        leaving = true;
+                    }
+                    _ => {}
                 }
             }
         }
+        for event in connection.take_events() {
+            let _ = tx.send(event);
+        }
+        for datagram in connection.take_outgoing() {
+            let _ = socket.send(&datagram).await;
+        }
+        if leaving {
+            return;
+        }
     }
 }
 
 impl Widget for &App {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        for player in &self.players {
-            player.render(area, buf);
+        let camera = Camera::new(area, &self.own_player, &self.map);
+        Terrain(&self.map, camera).render(area, buf);
+        let players = self.interpolation.players_at(Instant::now());
+        for player in &players {
+            PlayerSquare(player, camera).render(area, buf);
+        }
+        PlayerSquare(&self.own_player, camera).render(area, buf);
+        let now = Instant::now();
+        for player in players.iter().chain([&self.own_player]) {
+            if let Some(frame) = self.emotes.frame(player.id, now) {
+                EmoteOverlay {
+                    player,
+                    frame,
+                    camera,
+                }
+                .render(area, buf);
+            }
+        }
+        // Labels go on top of every square so none is hidden by a neighbour.
+        for player in &players {
+            NameTag {
+                player,
+                camera,
+                own: false,
+            }
+            .render(area, buf);
+        }
+        NameTag {
+            player: &self.own_player,
+            camera,
+            own: true,
+        }
+        .render(area, buf);
+        for player in players.iter().chain([&self.own_player]) {
+            if let Some(text) = self.chat.bubble(player.id, now) {
+                SpeechBubble {
+                    player,
+                    text,
+                    camera,
+                }
+                .render(area, buf);
+            }
+        }
+
+        ChatPanel(&self.chat).render(area, buf);
+
+        if self.show_minimap {
+            Minimap {
+                map: &self.map,
+                room: &self.room,
+                players: &players,
+                own_player: &self.own_player,
+                visible: camera.visible(&self.map),
+            }
+            .render(area, buf);
+        }
+
+        let notice = match self.status {
+            ConnectionStatus::Connecting => Some("Connecting to server..."),
+            ConnectionStatus::Connected => None,
+            ConnectionStatus::Disconnected => Some("Disconnected from server"),
+        };
+        if let Some(notice) = notice {
+            let line = Line::from(notice)
+                .fg(Color::White)
+                .bg(Color::Red)
+                .centered();
+            line.render(Rect { height: 1, ..area }, buf);
         }
-        self.own_player.render(area, buf);
     }
 }
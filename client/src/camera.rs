@@ -0,0 +1,72 @@
+use ratatui::prelude::{Position, Rect};
+use roam_protocol::{Map, Player};
+
+/// Which part of the world is on screen.
+///
+/// The camera keeps our own square in the middle of the area, but stops at
+/// the edges of the map so no empty space is shown past them. Maps smaller
+/// than the area are centred instead.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    area: Rect,
+    /// World coordinates of the top left cell of the area. Negative when the
+    /// map is smaller than the area.
+    left: i32,
+    top: i32,
+}
+
+impl Camera {
+    pub fn new(area: Rect, focus: &Player, map: &Map) -> Self {
+        // Squares are two cells wide; centre on the middle of ours.
+        let left = axis(
+            i32::from(focus.x) + 1,
+            i32::from(area.width),
+            i32::from(map.width()),
+        );
+        let top = axis(
+            i32::from(focus.y),
+            i32::from(area.height),
+            i32::from(map.height()),
+        );
+        Self { area, left, top }
+    }
+
+    /// The screen cell showing world cell `(x, y)`, if it is in view.
+    pub fn to_screen(self, x: u16, y: u16) -> Option<Position> {
+        let column = i32::from(x) - self.left;
+        let row = i32::from(y) - self.top;
+        let column = u16::try_from(column)
+            .ok()
+            .filter(|c| *c < self.area.width)?;
+        let row = u16::try_from(row).ok().filter(|r| *r < self.area.height)?;
+        Some(Position::new(self.area.x + column, self.area.y + row))
+    }
+
+    /// The world cell shown at screen cell `position`, if there is one.
+    pub fn to_world(self, position: Position) -> Option<(u16, u16)> {
+        let x = i32::from(position.x.checked_sub(self.area.x)?) + self.left;
+        let y = i32::from(position.y.checked_sub(self.area.y)?) + self.top;
+        Some((u16::try_from(x).ok()?, u16::try_from(y).ok()?))
+    }
+
+    /// The part of the map in view, in world coordinates.
+    pub fn visible(self, map: &Map) -> Rect {
+        // Both values fit in a u16: they lie between 0 and the map size.
+        let span = |start: i32, len: u16, world: u16| {
+            let start = start.clamp(0, i32::from(world));
+            let end = (start + i32::from(len)).min(i32::from(world));
+            (start as u16, (end - start) as u16)
+        };
+        let (x, width) = span(self.left, self.area.width, map.width());
+        let (y, height) = span(self.top, self.area.height, map.height());
+        Rect::new(x, y, width, height)
+    }
+}
+
+/// First visible world coordinate along one axis.
+fn axis(focus: i32, view: i32, world: i32) -> i32 {
+    if world <= view {
+        return -(view - world) / 2;
+    }
+    (focus - view / 2).clamp(0, world - view)
+}
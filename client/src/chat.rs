@@ -0,0 +1,317 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    prelude::{Buffer, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Clear, Widget},
+};
+use roam_protocol::{ChatMessage, ChatRequest, ChatScope, MAX_CHAT_LENGTH, PlayerId};
+
+/// Messages kept in the scrollback.
+const MAX_SCROLLBACK: usize = 200;
+
+/// Rows of messages shown at once.
+const VISIBLE_ROWS: u16 = 6;
+
+/// Widest the panel gets, border included.
+const MAX_WIDTH: u16 = 60;
+
+/// How long the panel stays up after the last message when nobody is typing
+/// or scrolling, so it does not cover the map for good.
+const FADE_AFTER: Duration = Duration::from_secs(15);
+
+/// How long a speech bubble stays above the speaker.
+const BUBBLE_DURATION: Duration = Duration::from_secs(6);
+
+/// A line in the scrollback. Lines without a sender are notices from the
+/// server, such as a message being refused.
+#[derive(Clone)]
+struct Entry {
+    time: String,
+    sender: Option<String>,
+    scope: ChatScope,
+    text: String,
+}
+
+/// Chat scrollback, the message being typed, if any, and what each player
+/// said last.
+#[derive(Clone)]
+pub struct Chat {
+    entries: VecDeque<Entry>,
+    /// How many of the newest entries are scrolled out of view.
+    scroll: usize,
+    draft: Option<String>,
+    /// Who the draft is for. It starts out as nearby players only and sticks
+    /// between messages.
+    scope: ChatScope,
+    last_activity: Option<Instant>,
+    /// Each player's latest message and when to stop showing it.
+    bubbles: HashMap<PlayerId, (String, Instant)>,
+}
+
+impl Default for Chat {
+    fn default() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            scroll: 0,
+            draft: None,
+            scope: ChatScope::Nearby,
+            last_activity: None,
+            bubbles: HashMap::new(),
+        }
+    }
+}
+
+impl Chat {
+    pub fn push(&mut self, message: ChatMessage) {
+        let now = Instant::now();
+        self.bubbles.retain(|_, (_, until)| *until > now);
+        self.bubbles
+            .insert(message.from, (message.text.clone(), now + BUBBLE_DURATION));
+        self.add(Entry {
+            time: clock(message.sent_at),
+            sender: Some(message.name),
+            scope: message.scope,
+            text: message.text,
+        });
+    }
+
+    /// What `player` said recently enough to still show above them.
+    pub fn bubble(&self, player: PlayerId, now: Instant) -> Option<&str> {
+        let (text, until) = self.bubbles.get(&player)?;
+        (*until > now).then_some(text.as_str())
+    }
+
+    /// Adds a notice from the server, stamped with our own clock.
+    pub fn notice(&mut self, text: String) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.add(Entry {
+            time: clock(now),
+            sender: None,
+            scope: ChatScope::Everyone,
+            text,
+        });
+    }
+
+    fn add(&mut self, entry: Entry) {
+        self.entries.push_back(entry);
+        if self.entries.len() > MAX_SCROLLBACK {
+            self.entries.pop_front();
+        }
+        // Someone reading back through the history keeps their place.
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.entries.len() - 1);
+        }
+        self.last_activity = Some(Instant::now());
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.draft.is_some()
+    }
+
+    pub fn start_typing(&mut self) {
+        self.draft = Some(String::new());
+    }
+
+    /// Switches the draft between everyone and just those nearby.
+    pub fn toggle_scope(&mut self) {
+        self.scope = match self.scope {
+            ChatScope::Everyone => ChatScope::Nearby,
+            ChatScope::Nearby => ChatScope::Everyone,
+        };
+    }
+
+    pub fn cancel_typing(&mut self) {
+        self.draft = None;
+        self.last_activity = Some(Instant::now());
+    }
+
+    pub fn type_char(&mut self, c: char) {
+        if let Some(draft) = &mut self.draft
+            && draft.chars().count() < MAX_CHAT_LENGTH
+        {
+            draft.push(c);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(draft) = &mut self.draft {
+            draft.pop();
+        }
+    }
+
+    /// Stops typing, returning the message to send unless it was blank.
+    pub fn take_draft(&mut self) -> Option<ChatRequest> {
+        let draft = self.draft.take()?;
+        self.last_activity = Some(Instant::now());
+        let text = draft.trim();
+        (!text.is_empty()).then(|| ChatRequest {
+            text: text.to_string(),
+            scope: self.scope,
+        })
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = (self.scroll + 1).min(self.entries.len().saturating_sub(1));
+        self.last_activity = Some(Instant::now());
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+        self.last_activity = Some(Instant::now());
+    }
+
+    fn is_visible(&self, now: Instant) -> bool {
+        self.is_typing()
+            || self.scroll > 0
+            || self
+                .last_activity
+                .is_some_and(|last| now.duration_since(last) < FADE_AFTER)
+    }
+}
+
+/// `HH:MM` of a Unix timestamp. Times are shown in UTC since the client may
+/// be running on an SSH host far from the player.
+fn clock(unix_seconds: u64) -> String {
+    let seconds = unix_seconds % (24 * 60 * 60);
+    format!("{:02}:{:02}", seconds / 3600, seconds / 60 % 60)
+}
+
+/// Splits `spans` into rows of at most `width` characters.
+fn wrap(spans: Vec<Span<'_>>, width: usize) -> Vec<Line<'static>> {
+    let mut rows = vec![Line::default()];
+    let mut used = 0;
+    for span in spans {
+        let mut rest: &str = &span.content;
+        while !rest.is_empty() {
+            if used == width {
+                rows.push(Line::default());
+                used = 0;
+            }
+            let take = rest
+                .char_indices()
+                .nth(width - used)
+                .map_or(rest.len(), |(i, _)| i);
+            let (head, tail) = rest.split_at(take);
+            used += head.chars().count();
+            if let Some(row) = rows.last_mut() {
+                row.push_span(Span::styled(head.to_string(), span.style));
+            }
+            rest = tail;
+        }
+    }
+    rows
+}
+
+/// The chat panel in the bottom left corner: recent messages, then the one
+/// being typed. It hides itself a while after the chat goes quiet.
+pub struct ChatPanel<'a>(pub &'a Chat);
+
+impl Widget for ChatPanel<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let chat = self.0;
+        if !chat.is_visible(Instant::now()) {
+            return;
+        }
+        let input_rows = u16::from(chat.is_typing());
+        let width = area.width.min(MAX_WIDTH);
+        let height = area.height.min(VISIBLE_ROWS + input_rows + 2);
+        if width < 3 || height < 3 {
+            return;
+        }
+        let panel = Rect {
+            x: area.x,
+            y: area.bottom() - height,
+            width,
+            height,
+        };
+        let title = if chat.scroll > 0 {
+            " Chat (scrolled back) "
+        } else if chat.is_typing() {
+            " Chat (Tab: say/all) "
+        } else {
+            " Chat "
+        };
+        let block = Block::bordered().title(title);
+        let inner = block.inner(panel);
+        Clear.render(panel, buf);
+        block.render(panel, buf);
+
+        let row_width = usize::from(inner.width);
+        let shown = chat.entries.len() - chat.scroll.min(chat.entries.len());
+        let mut rows: Vec<Line> = chat
+            .entries
+            .iter()
+            .take(shown)
+            .flat_map(|entry| {
+                let mut spans = vec![Span::styled(
+                    format!("[{}] ", entry.time),
+                    Style::new().fg(Color::DarkGray),
+                )];
+                match &entry.sender {
+                    Some(sender) => {
+                        let verb = match entry.scope {
+                            ChatScope::Everyone => "",
+                            ChatScope::Nearby => " says",
+                        };
+                        spans.push(Span::styled(
+                            format!("{sender}{verb}: "),
+                            Style::new().add_modifier(Modifier::BOLD),
+                        ));
+                        spans.push(Span::raw(entry.text.as_str()));
+                    }
+                    None => spans.push(Span::styled(
+                        entry.text.as_str(),
+                        Style::new().fg(Color::LightRed),
+                    )),
+                }
+                wrap(spans, row_width)
+            })
+            .collect();
+
+        // The newest message sits right above the input line.
+        let message_rows = inner.height - input_rows;
+        let skip = rows.len().saturating_sub(usize::from(message_rows));
+        let top = inner.y + message_rows - (rows.len() - skip) as u16;
+        for (i, row) in rows.drain(skip..).enumerate() {
+            row.render(
+                Rect {
+                    y: top + i as u16,
+                    height: 1,
+                    ..inner
+                },
+                buf,
+            );
+        }
+
+        if let Some(draft) = &chat.draft {
+            let prompt = match chat.scope {
+                ChatScope::Everyone => "all> ",
+                ChatScope::Nearby => "say> ",
+            };
+            // Keep the end of a long message in view, where the cursor is.
+            let visible = row_width.saturating_sub(prompt.len() + 1);
+            let skip = draft.chars().count().saturating_sub(visible);
+            let tail: String = draft.chars().skip(skip).collect();
+            Line::from(vec![
+                Span::raw(prompt).yellow(),
+                Span::raw(tail),
+                Span::raw(" ").reversed(),
+            ])
+            .render(
+                Rect {
+                    y: inner.bottom() - 1,
+                    height: 1,
+                    ..inner
+                },
+                buf,
+            );
+        }
+    }
+}
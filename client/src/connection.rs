@@ -0,0 +1,315 @@
+use std::{
+    collections::VecDeque,
+    env,
+    time::{Duration, Instant},
+};
+
+use roam_protocol::{
+    ClientMessage, Connect, Encoding, Map, MapChunk, PROTOCOL_VERSION, PlayerColor, PlayerSet,
+    ServerMessage, Snapshot, Welcome,
+};
+
+use crate::app::Event;
+
+/// How many decoded snapshots are kept as possible delta baselines.
+const SNAPSHOT_HISTORY: usize = 32;
+
+/// How often we tell the server we are still here.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the server may stay silent before we consider it gone. It answers
+/// every heartbeat, so this covers several lost round trips.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay before the first reconnect attempt, doubled after every failure up
+/// to [`MAX_RECONNECT_DELAY`].
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(15);
+
+/// How long a room's map may take to arrive in full before we connect again
+/// to have it sent over.
+const MAP_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// How this client asks to appear to everyone else. Anything left unset is
+/// picked by the server.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub name: Option<String>,
+    pub color: Option<PlayerColor>,
+    pub glyph: Option<char>,
+}
+
+/// Protocol state for the link to the game server, independent of how the
+/// datagrams are actually sent so the sync and async loops can share it.
+pub struct Connection {
+    profile: Profile,
+    encoding: Encoding,
+    status: ConnectionStatus,
+    last_heard: Instant,
+    last_heartbeat: Instant,
+    resume_token: Option<u64>,
+    /// The room we were last welcomed into.
+    room: Option<u16>,
+    /// A room we have been welcomed into whose map is still arriving.
+    entering: Option<Entering>,
+    reconnect_delay: Duration,
+    next_reconnect: Instant,
+    latest: Option<u32>,
+    snapshots: VecDeque<(u32, PlayerSet)>,
+    outgoing: Vec<Vec<u8>>,
+    events: Vec<Event>,
+}
+
+impl Connection {
+    pub fn new(profile: Profile) -> Self {
+        let now = Instant::now();
+        Self {
+            profile,
+            encoding: Encoding::Json,
+            status: ConnectionStatus::Connecting,
+            last_heard: now,
+            last_heartbeat: now,
+            resume_token: None,
+            room: None,
+            entering: None,
+            reconnect_delay: MIN_RECONNECT_DELAY,
+            next_reconnect: now + MIN_RECONNECT_DELAY,
+            latest: None,
+            snapshots: VecDeque::new(),
+            outgoing: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Encodes the handshake this client opens with. It is always JSON so any
+    /// server can read it; setting `PROTOCOL_ENCODING=json` asks the server
+    /// to keep using the human-readable encoding instead of binary. When
+    /// reconnecting it carries the resume token from the last session.
+    pub fn connect(&self) -> Vec<u8> {
+        let encoding = env::var("PROTOCOL_ENCODING")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default();
+        let message = ClientMessage::Connect(Connect {
+            version: PROTOCOL_VERSION,
+            encoding,
+            resume: self.resume_token,
+            name: self.profile.name.clone().unwrap_or_default(),
+            color: self.profile.color,
+            glyph: self.profile.glyph,
+        });
+        message.encode(Encoding::Json)
+    }
+
+    /// Queues a message for the server in the negotiated encoding.
+    pub fn send(&mut self, message: ClientMessage) {
+        self.outgoing.push(message.encode(self.encoding));
+    }
+
+    /// Datagrams waiting to be sent to the server.
+    pub fn take_outgoing(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Events waiting to be handed to the app.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Sends heartbeats, notices when the server has gone quiet and retries
+    /// the handshake until it answers. Must be called regularly, even when no
+    /// datagrams arrive.
+    pub fn poll(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_heartbeat) >= HEARTBEAT_INTERVAL {
+            self.last_heartbeat = now;
+            self.send(ClientMessage::Heartbeat);
+        }
+        if self.status == ConnectionStatus::Connected
+            && now.duration_since(self.last_heard) > SERVER_TIMEOUT
+        {
+            self.set_status(ConnectionStatus::Disconnected);
+            self.reconnect_delay = MIN_RECONNECT_DELAY;
+            self.next_reconnect = now;
+        }
+        if self.status != ConnectionStatus::Connected && now >= self.next_reconnect {
+            let handshake = self.connect();
+            self.outgoing.push(handshake);
+            self.next_reconnect = now + self.reconnect_delay;
+            self.reconnect_delay = (self.reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+        // The server only sends the map a few times, so if parts of it are
+        // still missing, connecting again has it sent over once more. Once
+        // we are in the room, the map we have is good enough.
+        let stuck = self.entering.as_mut().filter(|entering| {
+            self.room != Some(entering.welcome.room)
+                && now.duration_since(entering.since) >= MAP_TIMEOUT
+        });
+        if let Some(entering) = stuck {
+            entering.since = now;
+            let handshake = self.connect();
+            self.outgoing.push(handshake);
+        }
+    }
+
+    pub fn handle_datagram(&mut self, bytes: &[u8]) {
+        let message = match ServerMessage::decode(bytes) {
+            Ok(message) => message,
+            // Nothing useful can be done with it, and printing would garble
+            // the screen.
+            Err(_) => return,
+        };
+
+        self.last_heard = Instant::now();
+        match message {
+            ServerMessage::Welcome(welcome) => self.handle_welcome(welcome),
+            ServerMessage::MapChunk(chunk) => self.handle_map_chunk(chunk),
+            ServerMessage::Snapshot(snapshot) => self.handle_snapshot(snapshot),
+            ServerMessage::PlayerLeft(id) => self.events.push(Event::PlayerLeft(id)),
+            ServerMessage::Heartbeat => {}
+            ServerMessage::MoveRejected(sequence) => {
+                self.events.push(Event::MoveRejected(sequence))
+            }
+            ServerMessage::Chat(message) => self.events.push(Event::Chat(message)),
+            ServerMessage::Emote(emote) => self.events.push(Event::Emote(emote)),
+            ServerMessage::Error(reason) => {
+                self.events.push(Event::ServerError(reason));
+                return;
+            }
+        }
+        self.set_status(ConnectionStatus::Connected);
+    }
+
+    /// Starts putting together the map for the room in `welcome`. A
+    /// `WELCOME` sent again for the same room keeps the rows we already have.
+    fn handle_welcome(&mut self, welcome: Welcome) {
+        self.encoding = welcome.encoding;
+        self.resume_token = Some(welcome.resume_token);
+        // The server holds back the map and snapshots until we show we got
+        // this. Once it has heard from us, it ignores the echo.
+        self.send(ClientMessage::Confirm(welcome.resume_token));
+        let size = (welcome.map_width, welcome.map_height);
+        match &mut self.entering {
+            Some(entering)
+                if entering.welcome.room == welcome.room
+                    && (entering.map.width(), entering.map.height()) == size =>
+            {
+                entering.welcome = welcome;
+            }
+            _ => {
+                self.entering = Some(Entering {
+                    map: Map::walls(size.0, size.1),
+                    received: vec![false; usize::from(size.1)],
+                    since: Instant::now(),
+                    welcome,
+                })
+            }
+        }
+        self.enter_if_complete();
+    }
+
+    fn handle_map_chunk(&mut self, chunk: MapChunk) {
+        let Some(entering) = &mut self.entering else {
+            return;
+        };
+        if entering.welcome.room != chunk.room || !entering.map.paste(chunk.row, &chunk.tiles) {
+            return;
+        }
+        let rows =
+            usize::from(chunk.row)..usize::from(chunk.row) + usize::from(chunk.tiles.height());
+        entering.received[rows].fill(true);
+        self.enter_if_complete();
+    }
+
+    /// Moves us into the room being entered once its whole map is here.
+    fn enter_if_complete(&mut self) {
+        if !self
+            .entering
+            .as_ref()
+            .is_some_and(|entering| entering.received.iter().all(|&row| row))
+        {
+            return;
+        }
+        let Some(Entering { welcome, map, .. }) = self.entering.take() else {
+            return;
+        };
+        self.room = Some(welcome.room);
+        // The server starts its snapshots over with every WELCOME.
+        self.latest = None;
+        self.snapshots.clear();
+        self.events.push(Event::Welcome(
+            welcome.player,
+            map,
+            welcome.room_name,
+            welcome.tick_rate,
+        ));
+    }
+
+    fn set_status(&mut self, status: ConnectionStatus) {
+        if self.status != status {
+            self.status = status;
+            self.events.push(Event::ConnectionStatus(status));
+        }
+    }
+
+    fn handle_snapshot(&mut self, snapshot: Snapshot) {
+        // Leaving these unacknowledged, including while the room's map is
+        // still arriving, makes the server send the WELCOME for the room
+        // again.
+        if self.room != Some(snapshot.room) {
+            return;
+        }
+        let baseline = match snapshot.baseline {
+            Some(baseline) => {
+                let Some((_, players)) = self
+                    .snapshots
+                    .iter()
+                    .find(|(sequence, _)| *sequence == baseline)
+                else {
+                    return;
+                };
+                Some(players)
+            }
+            None => None,
+        };
+        let players = snapshot.apply(baseline);
+        self.send(ClientMessage::Ack(snapshot.sequence));
+
+        self.snapshots
+            .push_back((snapshot.sequence, players.clone()));
+        if self.snapshots.len() > SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+
+        // Datagrams can arrive out of order; never step back in time.
+        if self
+            .latest
+            .is_some_and(|latest| snapshot.sequence <= latest)
+        {
+            return;
+        }
+        self.latest = Some(snapshot.sequence);
+        self.events.push(Event::SetPlayers(
+            players.into_values().collect(),
+            snapshot.input,
+            snapshot.tick,
+        ));
+    }
+}
+
+/// A room we have been welcomed into, waiting for the rest of its map.
+struct Entering {
+    welcome: Welcome,
+    map: Map,
+    /// Which rows of the map have arrived.
+    received: Vec<bool>,
+    /// When we were welcomed in, or last asked to be again.
+    since: Instant,
+}
@@ -0,0 +1,81 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    prelude::{Buffer, Rect},
+    widgets::Widget,
+};
+use roam_protocol::{Emote, Player, PlayerEmote, PlayerId};
+
+use crate::camera::Camera;
+
+/// How long an emote plays for.
+const EMOTE_DURATION: Duration = Duration::from_secs(3);
+
+/// How long each frame of an emote stays up.
+const FRAME_DURATION: Duration = Duration::from_millis(250);
+
+/// The frames of an emote, each two cells wide to cover a square.
+fn frames(emote: Emote) -> &'static [&'static str] {
+    match emote {
+        Emote::Wave => &["o/", "o-"],
+        Emote::Dance => &["\\o", "o/", "<o", "o>"],
+        Emote::Exclaim => &["!!", "  "],
+        Emote::Question => &["? ", " ?"],
+    }
+}
+
+/// Emotes currently playing, by who is playing them.
+#[derive(Clone, Default)]
+pub struct Emotes {
+    playing: HashMap<PlayerId, (Emote, Instant)>,
+}
+
+impl Emotes {
+    /// Starts an emote, replacing whatever that player was playing.
+    pub fn play(&mut self, emote: PlayerEmote, now: Instant) {
+        self.playing
+            .retain(|_, (_, started)| now.duration_since(*started) < EMOTE_DURATION);
+        self.playing.insert(emote.player, (emote.emote, now));
+    }
+
+    pub fn remove(&mut self, player: PlayerId) {
+        self.playing.remove(&player);
+    }
+
+    /// The frame to show over `player` at `now`, if they are emoting.
+    pub fn frame(&self, player: PlayerId, now: Instant) -> Option<&'static str> {
+        let (emote, started) = self.playing.get(&player)?;
+        let elapsed = now.duration_since(*started);
+        if elapsed >= EMOTE_DURATION {
+            return None;
+        }
+        let frames = frames(*emote);
+        let index = (elapsed.as_millis() / FRAME_DURATION.as_millis()) as usize % frames.len();
+        Some(frames[index])
+    }
+}
+
+/// Draws an emote frame over a player's square, keeping the square's
+/// colours. Cells out of view are left out.
+pub struct EmoteOverlay<'a> {
+    pub player: &'a Player,
+    pub frame: &'static str,
+    pub camera: Camera,
+}
+
+impl Widget for EmoteOverlay<'_> {
+    fn render(self, _area: Rect, buf: &mut Buffer) {
+        for (dx, c) in (0..).zip(self.frame.chars()) {
+            let Some(position) = self
+                .camera
+                .to_screen(self.player.x.saturating_add(dx), self.player.y)
+            else {
+                continue;
+            };
+            buf[position].set_char(c);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use roam_protocol::{Player, PlayerId, PlayerSet};
+
+/// How far in the past remote players are drawn. A few snapshot intervals at
+/// 30 Hz, so one or two lost datagrams still leave a snapshot on either side
+/// of the render time.
+const INTERPOLATION_DELAY: Duration = Duration::from_millis(100);
+
+/// How long we keep moving a player along its last known velocity once we
+/// run out of snapshots. After that we stop guessing and show the last
+/// position the server sent.
+const MAX_EXTRAPOLATION: Duration = Duration::from_millis(100);
+
+/// Upper bound on buffered snapshots in case rendering stalls.
+const MAX_BUFFERED: usize = 64;
+
+/// Recent remote player sets, by the server tick they were taken on.
+#[derive(Clone, Default)]
+pub struct Interpolation {
+    /// How long a server tick lasts; zero until a `WELCOME` tells us.
+    tick: Duration,
+    /// A server tick and when we take it to have happened here, which puts
+    /// the server's ticks on our clock. It follows the snapshots that arrive
+    /// soonest, as those were held up least on the way.
+    anchor: Option<(u32, Instant)>,
+    snapshots: VecDeque<(u32, PlayerSet)>,
+}
+
+impl Interpolation {
+    /// Forgets everything buffered, for a server ticking `tick_rate` times a
+    /// second.
+    pub fn reset(&mut self, tick_rate: u32) {
+        self.tick = Duration::from_secs(1) / tick_rate.max(1);
+        self.anchor = None;
+        self.snapshots.clear();
+    }
+
+    pub fn push(&mut self, tick: u32, received: Instant, players: Vec<Player>) {
+        if self.tick.is_zero() {
+            return;
+        }
+        // Datagrams can arrive out of order; one older than what we have
+        // would only send players backwards.
+        if let Some((latest, _)) = self.snapshots.back()
+            && tick.wrapping_sub(*latest) as i32 <= 0
+        {
+            return;
+        }
+
+        // A snapshot that arrives earlier than the anchor predicts shows the
+        // server is further along than we thought. One that arrives too late
+        // to be drawn means it has fallen behind, or our clocks drift apart.
+        let arrival = self.ticks(tick) * self.tick.as_secs_f64();
+        let waited = self.since_anchor(received) - arrival;
+        if self.anchor.is_none() || waited < 0.0 || waited > INTERPOLATION_DELAY.as_secs_f64() {
+            self.anchor = Some((tick, received));
+        }
+
+        let players = players.into_iter().map(|p| (p.id, p)).collect();
+        self.snapshots.push_back((tick, players));
+
+        // Keep the newest snapshot at or before the render time; anything
+        // older can no longer be interpolated from.
+        let render_tick = self.render_tick(received);
+        while self.snapshots.len() > MAX_BUFFERED
+            || self
+                .snapshots
+                .get(1)
+                .is_some_and(|(tick, _)| self.ticks(*tick) <= render_tick)
+        {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Drops a player from every buffered snapshot so they vanish at once.
+    pub fn remove(&mut self, id: PlayerId) {
+        for (_, players) in &mut self.snapshots {
+            players.remove(&id);
+        }
+    }
+
+    /// Where remote players should be drawn at `now`.
+    pub fn players_at(&self, now: Instant) -> Vec<Player> {
+        let t = self.render_tick(now);
+        let Some(index) = self
+            .snapshots
+            .iter()
+            .rposition(|(tick, _)| self.ticks(*tick) <= t)
+        else {
+            // Nothing old enough yet; show the oldest we have.
+            return self
+                .snapshots
+                .front()
+                .map(|(_, players)| players.values().cloned().collect())
+                .unwrap_or_default();
+        };
+        let (tick, players) = &self.snapshots[index];
+        let at = self.ticks(*tick);
+
+        if let Some((next_tick, next)) = self.snapshots.get(index + 1) {
+            let alpha = (t - at) / (self.ticks(*next_tick) - at);
+            return players
+                .values()
+                .map(|player| match next.get(&player.id) {
+                    Some(to) => lerp(player, to, alpha as f32),
+                    None => player.clone(),
+                })
+                .collect();
+        }
+
+        let ahead = t - at;
+        let max_ahead = MAX_EXTRAPOLATION.as_secs_f64() / self.tick.as_secs_f64();
+        let previous = index.checked_sub(1).map(|i| &self.snapshots[i]);
+        match previous {
+            Some((previous_tick, previous)) if ahead <= max_ahead => {
+                let alpha = 1.0 + ahead / (at - self.ticks(*previous_tick));
+                players
+                    .values()
+                    .map(|player| match previous.get(&player.id) {
+                        Some(from) => lerp(from, player, alpha as f32),
+                        None => player.clone(),
+                    })
+                    .collect()
+            }
+            _ => players.values().cloned().collect(),
+        }
+    }
+
+    /// How many ticks `tick` comes after the anchor; negative if before.
+    /// Counting from the anchor keeps the numbers small across the wrap.
+    fn ticks(&self, tick: u32) -> f64 {
+        let anchor = self.anchor.map_or(tick, |(anchor, _)| anchor);
+        f64::from(tick.wrapping_sub(anchor) as i32)
+    }
+
+    /// Seconds from the anchor to `now`; negative if before.
+    fn since_anchor(&self, now: Instant) -> f64 {
+        let Some((_, anchor)) = self.anchor else {
+            return 0.0;
+        };
+        now.saturating_duration_since(anchor).as_secs_f64()
+            - anchor.saturating_duration_since(now).as_secs_f64()
+    }
+
+    /// The server tick to draw at `now`, counted from the anchor.
+    fn render_tick(&self, now: Instant) -> f64 {
+        (self.since_anchor(now) - INTERPOLATION_DELAY.as_secs_f64()) / self.tick.as_secs_f64()
+    }
+}
+
+/// Blends two positions of the same player; an `alpha` above 1 extrapolates.
+fn lerp(from: &Player, to: &Player, alpha: f32) -> Player {
+    // Casting saturates, so extrapolating past the origin stops at zero.
+    let blend =
+        |a: u16, b: u16| (f32::from(a) + (f32::from(b) - f32::from(a)) * alpha).round() as u16;
+    Player {
+        x: blend(from.x, to.x),
+        y: blend(from.y, to.y),
+        ..to.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ten ticks a second, so a tick lasts as long as the interpolation
+    /// delay.
+    const TICK: Duration = Duration::from_millis(100);
+
+    fn at(x: u16) -> Vec<Player> {
+        vec![Player {
+            id: PlayerId(1),
+            name: "ada".to_string(),
+            color: Default::default(),
+            glyph: None,
+            presence: Default::default(),
+            x,
+            y: 0,
+        }]
+    }
+
+    fn x_at(interpolation: &Interpolation, now: Instant) -> Option<u16> {
+        interpolation.players_at(now).first().map(|player| player.x)
+    }
+
+    fn interpolation() -> Interpolation {
+        let mut interpolation = Interpolation::default();
+        interpolation.reset(10);
+        interpolation
+    }
+
+    #[test]
+    fn nothing_is_buffered_before_the_tick_rate_is_known() {
+        let mut interpolation = Interpolation::default();
+        interpolation.push(1, Instant::now(), at(0));
+        assert_eq!(x_at(&interpolation, Instant::now()), None);
+    }
+
+    #[test]
+    fn blends_between_ticks() {
+        let mut interpolation = interpolation();
+        let start = Instant::now();
+        interpolation.push(100, start, at(0));
+        interpolation.push(101, start + TICK, at(10));
+        assert_eq!(x_at(&interpolation, start + TICK), Some(0));
+        assert_eq!(x_at(&interpolation, start + TICK * 3 / 2), Some(5));
+        assert_eq!(x_at(&interpolation, start + TICK * 2), Some(10));
+    }
+
+    #[test]
+    fn arrival_jitter_does_not_bend_the_blend() {
+        let mut interpolation = interpolation();
+        let start = Instant::now();
+        interpolation.push(100, start, at(0));
+        // Held up on the way, then arriving right before the next one.
+        interpolation.push(101, start + Duration::from_millis(190), at(10));
+        interpolation.push(102, start + TICK * 2, at(20));
+        assert_eq!(x_at(&interpolation, start + TICK * 5 / 2), Some(15));
+    }
+
+    #[test]
+    fn extrapolates_only_briefly() {
+        let mut interpolation = interpolation();
+        let start = Instant::now();
+        interpolation.push(100, start, at(0));
+        interpolation.push(101, start + TICK, at(2));
+        assert_eq!(x_at(&interpolation, start + TICK * 5 / 2), Some(3));
+        assert_eq!(x_at(&interpolation, start + TICK * 4), Some(2));
+    }
+
+    #[test]
+    fn early_snapshot_moves_the_timeline() {
+        let mut interpolation = interpolation();
+        let start = Instant::now();
+        interpolation.push(100, start, at(0));
+        // Tick 102 was due at start + 2 ticks, so the first one was late.
+        interpolation.push(102, start + TICK, at(20));
+        assert_eq!(x_at(&interpolation, start + TICK * 2), Some(20));
+    }
+
+    #[test]
+    fn old_ticks_are_ignored() {
+        let mut interpolation = interpolation();
+        let start = Instant::now();
+        interpolation.push(101, start, at(10));
+        interpolation.push(100, start, at(0));
+        interpolation.push(101, start, at(0));
+        assert_eq!(interpolation.snapshots.len(), 1);
+        assert_eq!(x_at(&interpolation, start + TICK * 2), Some(10));
+    }
+
+    #[test]
+    fn blends_across_the_tick_wrapping() {
+        let mut interpolation = interpolation();
+        let start = Instant::now();
+        interpolation.push(u32::MAX, start, at(0));
+        interpolation.push(0, start + TICK, at(10));
+        assert_eq!(x_at(&interpolation, start + TICK * 3 / 2), Some(5));
+    }
+
+    #[test]
+    fn removed_players_vanish_at_once() {
+        let mut interpolation = interpolation();
+        let start = Instant::now();
+        interpolation.push(100, start, at(0));
+        interpolation.push(101, start + TICK, at(10));
+        interpolation.remove(PlayerId(1));
+        assert_eq!(x_at(&interpolation, start + TICK * 3 / 2), None);
+    }
+}
@@ -1,10 +1,20 @@
 mod app;
+mod camera;
+mod chat;
+mod connection;
+mod emote;
+mod interpolation;
+mod minimap;
 mod player;
+mod prediction;
 mod server;
+mod terrain;
 
-use crate::player::Player;
 use crate::server::app_server::AppServer;
+use anyhow::anyhow;
 use clap::{Arg, Command};
+use connection::Profile;
+use roam_protocol::{PlayerColor, is_valid_glyph, validate_name};
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
@@ -18,60 +28,105 @@ async fn main() -> Result<(), anyhow::Error> {
                 .help("Run in server mode (SSH server on port 22)")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("name")
+                .short('n')
+                .long("name")
+                .help("Name shown above your player in local mode"),
+        )
+        .arg(
+            Arg::new("color")
+                .short('c')
+                .long("color")
+                .value_parser(clap::value_parser!(PlayerColor))
+                .help("Colour of your player in local mode, picked by the server if unset"),
+        )
+        .arg(
+            Arg::new("glyph")
+                .short('g')
+                .long("glyph")
+                .value_parser(clap::value_parser!(char))
+                .help("Character drawn on your player in local mode"),
+        )
         .get_matches();
 
     let server_mode = matches.get_flag("server");
+    let name = matches.get_one::<String>("name").cloned();
+    if let Some(name) = &name {
+        validate_name(name).map_err(|e| anyhow!("Invalid --name {name:?}: {e}"))?;
+    }
+    let glyph = matches.get_one::<char>("glyph").copied();
+    if let Some(glyph) = glyph
+        && !is_valid_glyph(glyph)
+    {
+        return Err(anyhow!(
+            "Invalid --glyph {glyph:?}: use a printable ASCII character"
+        ));
+    }
+    let profile = Profile {
+        name,
+        color: matches.get_one::<PlayerColor>("color").copied(),
+        glyph,
+    };
 
     if server_mode {
         let mut server = AppServer::new();
         server.run().await
     } else {
-        run_local().await
+        run_local(profile).await
     }
 }
 
-async fn run_local() -> Result<(), anyhow::Error> {
+async fn run_local(profile: Profile) -> Result<(), anyhow::Error> {
     let mut terminal = ratatui::init();
 
     let (event_tx, event_rx) = std::sync::mpsc::channel::<app::Event>();
     let (own_tx, own_rx) = std::sync::mpsc::channel::<app::Event>();
 
+    // Reading the terminal blocks, so it gets a thread of its own rather
+    // than tying up a runtime worker the render ticks need.
     let tx_to_input_events = event_tx.clone();
+    std::thread::spawn(move || handle_input_events(tx_to_input_events));
+
+    let tx_to_render_ticks = event_tx.clone();
     tokio::spawn(async move {
-        handle_input_events(tx_to_input_events).await;
+        // Remote players move between snapshots, so redraw on a timer too.
+        let mut interval = tokio::time::interval(app::RENDER_INTERVAL);
+        loop {
+            interval.tick().await;
+            if tx_to_render_ticks.send(app::Event::Render).is_err() {
+                break;
+            }
+        }
     });
 
     let tx_to_background_progress_events = event_tx.clone();
-    tokio::spawn(async move {
-        tokio::task::spawn_blocking(move || {
-            app::run_background_connection(tx_to_background_progress_events, own_rx);
-        });
+    let background = tokio::task::spawn_blocking(move || {
+        app::run_background_connection(tx_to_background_progress_events, own_rx, profile);
     });
 
-    let players: Vec<Player> = vec![Player { x: 0, y: 0 }];
-
-    let mut app = app::App {
-        exit: false,
-        players,
-        own_player: Player { x: 0, y: 0 },
-    };
+    let mut app = app::App::new();
 
     // App runs on the main thread.
-    let app_result =
-        tokio::task::spawn_blocking(move || app.run(&mut terminal, event_rx, own_tx)).await??;
+    let result =
+        tokio::task::spawn_blocking(move || app.run(&mut terminal, event_rx, own_tx)).await;
 
+    // Give the terminal back before anything can return early, so a failed
+    // connection never leaves it in raw mode.
     ratatui::restore();
-    Ok(app_result)
+
+    // Let the connection tell the server we left before the process exits.
+    if let Err(e) = background.await {
+        eprintln!("Connection failed: {e}");
+    }
+    result??;
+    Ok(())
 }
 
-async fn handle_input_events(tx: std::sync::mpsc::Sender<app::Event>) {
+fn handle_input_events(tx: std::sync::mpsc::Sender<app::Event>) {
     loop {
-        match crossterm::event::read().unwrap() {
-            crossterm::event::Event::Key(key_event) => {
-                let _ = tx.send(app::Event::Input(key_event));
-            }
-            _ => {}
+        if let crossterm::event::Event::Key(key_event) = crossterm::event::read().unwrap() {
+            let _ = tx.send(app::Event::Input(key_event));
         }
-        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
     }
 }
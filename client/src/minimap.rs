@@ -0,0 +1,136 @@
+use ratatui::{
+    prelude::{Buffer, Rect},
+    style::Color,
+    widgets::{Block, Clear, Widget},
+};
+use roam_protocol::{Map, Player, Tile};
+
+use crate::player;
+
+/// Largest minimap, in terminal cells, not counting its border.
+const MAX_WIDTH: u16 = 40;
+const MAX_HEIGHT: u16 = 12;
+
+/// An overview of the room's map in the top right corner, with every player
+/// and the part of the world currently on screen. The room's name is its
+/// title.
+///
+/// Each terminal cell shows two pixels stacked with a half block, which
+/// makes the pixels roughly square. A world cell is twice as tall as it is
+/// wide, so it covers one pixel across and two down before scaling.
+pub struct Minimap<'a> {
+    pub map: &'a Map,
+    pub room: &'a str,
+    pub players: &'a [Player],
+    pub own_player: &'a Player,
+    /// The part of the map in view, in world coordinates.
+    pub visible: Rect,
+}
+
+impl Minimap<'_> {
+    /// How many world columns each pixel covers.
+    fn scale(&self) -> u16 {
+        let width = self.map.width().div_ceil(MAX_WIDTH);
+        let height = self.map.height().div_ceil(MAX_HEIGHT);
+        width.max(height).max(1)
+    }
+
+    /// Colour of the pixel at `(px, py)` from the terrain alone.
+    fn terrain(&self, scale: u16, px: u16, py: u16) -> Color {
+        let xs = px * scale..(px + 1) * scale;
+        let y_start = py * scale / 2;
+        let ys = y_start..((py + 1) * scale / 2).max(y_start + 1);
+        ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+            .filter_map(|(x, y)| self.map.tile(x, y))
+            .max_by_key(|tile| priority(*tile))
+            .map(colour)
+            .unwrap_or(Color::Reset)
+    }
+}
+
+/// Which tile a pixel covering several shows. Walls win so that thin ones do
+/// not disappear when the map is scaled down.
+fn priority(tile: Tile) -> u8 {
+    match tile {
+        Tile::Floor => 0,
+        Tile::Social => 1,
+        Tile::Door => 2,
+        Tile::Water => 3,
+        Tile::Wall => 4,
+    }
+}
+
+fn colour(tile: Tile) -> Color {
+    match tile {
+        Tile::Floor => Color::Black,
+        Tile::Social => Color::Magenta,
+        Tile::Door => Color::Yellow,
+        Tile::Water => Color::Blue,
+        Tile::Wall => Color::Gray,
+    }
+}
+
+impl Widget for Minimap<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if self.map.width() == 0 {
+            return;
+        }
+        let scale = self.scale();
+        let width = self.map.width().div_ceil(scale);
+        let pixel_rows = (self.map.height() * 2).div_ceil(scale);
+        let height = pixel_rows.div_ceil(2);
+
+        let outer = Rect {
+            x: area.right().saturating_sub(width + 2),
+            y: area.y,
+            width: (width + 2).min(area.width),
+            height: (height + 2).min(area.height),
+        };
+        Clear.render(outer, buf);
+        let block = Block::bordered().title(self.room);
+        let inner = block.inner(outer);
+        block.render(outer, buf);
+
+        let mut pixels = vec![Color::Reset; usize::from(width) * usize::from(height) * 2];
+        let index = |px: u16, py: u16| usize::from(py) * usize::from(width) + usize::from(px);
+        for py in 0..pixel_rows {
+            for px in 0..width {
+                pixels[index(px, py)] = self.terrain(scale, px, py);
+            }
+        }
+
+        // Outline what is on screen.
+        let view = self.visible;
+        if !view.is_empty() {
+            let (left, right) = (view.x / scale, (view.right() - 1) / scale);
+            let (top, bottom) = (view.y * 2 / scale, (view.bottom() * 2 - 1) / scale);
+            for px in left..=right {
+                pixels[index(px, top)] = Color::DarkGray;
+                pixels[index(px, bottom)] = Color::DarkGray;
+            }
+            for py in top..=bottom {
+                pixels[index(left, py)] = Color::DarkGray;
+                pixels[index(right, py)] = Color::DarkGray;
+            }
+        }
+
+        // Everyone shows in their own colour except us, in white, so we can
+        // find ourselves at a glance.
+        let players = self.players.iter().map(|p| (p, player::color(p.color)));
+        for (player, colour) in players.chain([(self.own_player, Color::White)]) {
+            let (px, py) = (player.x / scale, player.y * 2 / scale);
+            if px < width && py < pixel_rows {
+                pixels[index(px, py)] = colour;
+            }
+        }
+
+        for row in 0..height.min(inner.height) {
+            for column in 0..width.min(inner.width) {
+                buf[(inner.x + column, inner.y + row)]
+                    .set_symbol("▀")
+                    .set_fg(pixels[index(column, row * 2)])
+                    .set_bg(pixels[index(column, row * 2 + 1)]);
+            }
+        }
+    }
+}
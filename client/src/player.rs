@@ -1,23 +1,139 @@
 use ratatui::{
     prelude::{Buffer, Rect},
-    style::Color,
+    style::{Color, Modifier, Style},
     widgets::Widget,
 };
 
-#[derive(Clone, serde::Deserialize, serde::Serialize)]
-pub struct Player {
-    pub x: u16,
-    pub y: u16,
+pub use roam_protocol::{Player, PlayerColor, PlayerId, Presence};
+
+use crate::camera::Camera;
+
+/// The terminal colour a palette colour is drawn with.
+pub fn color(color: PlayerColor) -> Color {
+    match color {
+        PlayerColor::Red => Color::Red,
+        PlayerColor::Green => Color::Green,
+        PlayerColor::Yellow => Color::Yellow,
+        PlayerColor::Blue => Color::Blue,
+        PlayerColor::Magenta => Color::Magenta,
+        PlayerColor::Cyan => Color::Cyan,
+        PlayerColor::Orange => Color::Rgb(255, 140, 0),
+        PlayerColor::Pink => Color::LightMagenta,
+    }
+}
+
+/// Text colour that stays readable on top of `color`.
+fn contrast(color: PlayerColor) -> Color {
+    match color {
+        PlayerColor::Red | PlayerColor::Blue | PlayerColor::Magenta => Color::White,
+        _ => Color::Black,
+    }
 }
 
-impl Widget for &Player {
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        let x = self.x.min(area.width.saturating_sub(2));
-        let y = self.y.min(area.height.saturating_sub(1));
+/// Draws a [`Player`] as a 2x1 block of their colour on top of the terrain,
+/// where the camera puts it, with their glyph in the first cell. Cells out of
+/// view are left out.
+pub struct PlayerSquare<'a>(pub &'a Player, pub Camera);
+
+impl Widget for PlayerSquare<'_> {
+    fn render(self, _area: Rect, buf: &mut Buffer) {
+        let style = Style::new()
+            .fg(contrast(self.0.color))
+            .bg(color(self.0.color));
         for dx in 0..2 {
-            for dy in 0..1 {
-                buf[(x.saturating_add(dx as u16), y.saturating_add(dy as u16))].set_bg(Color::Red);
-            }
+            let Some(position) = self.1.to_screen(self.0.x.saturating_add(dx), self.0.y) else {
+                continue;
+            };
+            let symbol = match self.0.glyph {
+                Some(glyph) if dx == 0 => glyph,
+                _ => ' ',
+            };
+            buf[position].set_char(symbol).set_style(style);
+        }
+    }
+}
+
+/// Draws a player's name on the row above their square, centred on it, with
+/// a note when they are away or busy. Our own name is drawn on a bar of our
+/// colour so we can spot ourselves in a crowd. Characters out of view are
+/// left out.
+pub struct NameTag<'a> {
+    pub player: &'a Player,
+    pub camera: Camera,
+    pub own: bool,
+}
+
+impl Widget for NameTag<'_> {
+    fn render(self, _area: Rect, buf: &mut Buffer) {
+        let Some(y) = self.player.y.checked_sub(1) else {
+            return;
+        };
+        let style = if self.own {
+            Style::new()
+                .fg(contrast(self.player.color))
+                .bg(color(self.player.color))
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::new().fg(Color::White)
+        };
+        let (note, note_style) = match self.player.presence {
+            Presence::Available => ("", style),
+            Presence::Away => (" (away)", Style::new().fg(Color::DarkGray)),
+            Presence::Busy => (" (busy)", Style::new().fg(Color::LightRed)),
+        };
+        let note_style = if self.own { style } else { note_style };
+        let label = format!("{}{note}", self.player.name);
+        // Labels are ASCII, so every byte takes one cell.
+        let len = label.len() as u16;
+        let start = self.player.x.saturating_add(1).saturating_sub(len / 2);
+        for (i, c) in label.chars().enumerate() {
+            let Some(position) = self.camera.to_screen(start.saturating_add(i as u16), y) else {
+                continue;
+            };
+            let style = if i < self.player.name.len() {
+                style
+            } else {
+                note_style
+            };
+            buf[position].set_char(c).set_style(style);
+        }
+    }
+}
+
+/// Widest a speech bubble gets, in characters. Longer messages are cut
+/// short; the chat log has the rest.
+const BUBBLE_WIDTH: usize = 24;
+
+/// Draws what a player just said on the row above their name tag, centred on
+/// their square, or below the square at the top edge of the map. Characters
+/// out of view are left out.
+pub struct SpeechBubble<'a> {
+    pub player: &'a Player,
+    pub text: &'a str,
+    pub camera: Camera,
+}
+
+impl Widget for SpeechBubble<'_> {
+    fn render(self, _area: Rect, buf: &mut Buffer) {
+        let y = self
+            .player
+            .y
+            .checked_sub(2)
+            .unwrap_or(self.player.y.saturating_add(1));
+        let mut text: String = self.text.chars().take(BUBBLE_WIDTH).collect();
+        if self.text.chars().count() > BUBBLE_WIDTH {
+            text.pop();
+            text.push('…');
+        }
+        let text = format!(" {text} ");
+        let len = text.chars().count() as u16;
+        let start = self.player.x.saturating_add(1).saturating_sub(len / 2);
+        let style = Style::new().fg(Color::Black).bg(Color::White);
+        for (i, c) in text.chars().enumerate() {
+            let Some(position) = self.camera.to_screen(start.saturating_add(i as u16), y) else {
+                continue;
+            };
+            buf[position].set_char(c).set_style(style);
         }
     }
 }
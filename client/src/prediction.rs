@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use roam_protocol::{Direction, Input, Map, Player};
+
+/// Inputs we have applied locally but the server has not confirmed yet.
+///
+/// Key presses move our own square straight away. When a snapshot arrives we
+/// start again from the position the server reports and replay every input
+/// it has not processed, so any disagreement is corrected without undoing
+/// moves that are still in flight.
+#[derive(Clone, Default)]
+pub struct Prediction {
+    next_sequence: u32,
+    pending: VecDeque<Input>,
+    /// Our player as of the latest snapshot.
+    confirmed: Option<Player>,
+}
+
+impl Prediction {
+    /// Applies `direction` to `player` and returns the numbered input to
+    /// send, or `None` if the map is in the way and there is nothing to send.
+    pub fn apply(&mut self, player: &mut Player, direction: Direction, map: &Map) -> Option<Input> {
+        (player.x, player.y) = direction.step(player, map)?;
+        self.next_sequence += 1;
+        let input = Input {
+            sequence: self.next_sequence,
+            direction,
+        };
+        self.pending.push_back(input);
+        Some(input)
+    }
+
+    /// Rebuilds our predicted position from the server's view of us, given
+    /// the last input sequence it applied.
+    pub fn reconcile(&mut self, player: Player, processed: u32, map: &Map) -> Player {
+        while self
+            .pending
+            .front()
+            .is_some_and(|input| input.sequence <= processed)
+        {
+            self.pending.pop_front();
+        }
+        self.confirmed = Some(player.clone());
+        self.replay(player, map)
+    }
+
+    /// Drops an input the server refused and returns our corrected position,
+    /// without waiting for the next snapshot.
+    pub fn reject(&mut self, sequence: u32, map: &Map) -> Option<Player> {
+        self.pending.retain(|input| input.sequence != sequence);
+        let confirmed = self.confirmed.clone()?;
+        Some(self.replay(confirmed, map))
+    }
+
+    fn replay(&self, mut player: Player, map: &Map) -> Player {
+        for input in &self.pending {
+            if let Some(position) = input.direction.step(&player, map) {
+                (player.x, player.y) = position;
+            }
+        }
+        player
+    }
+
+    /// Forgets unconfirmed inputs; a new session starts from the server's
+    /// position.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.confirmed = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use roam_protocol::PlayerId;
+
+    use super::*;
+
+    /// A corridor with room for four squares side by side.
+    fn corridor() -> Map {
+        "........".parse().unwrap()
+    }
+
+    fn at(x: u16) -> Player {
+        Player {
+            id: PlayerId(1),
+            name: "ada".to_string(),
+            color: Default::default(),
+            glyph: None,
+            presence: Default::default(),
+            x,
+            y: 0,
+        }
+    }
+
+    /// Predicts `count` steps right from `player`, returning where it ends up.
+    fn walk_right(prediction: &mut Prediction, mut player: Player, count: usize) -> Player {
+        for _ in 0..count {
+            prediction.apply(&mut player, Direction::Right, &corridor());
+        }
+        player
+    }
+
+    #[test]
+    fn apply_numbers_only_moves_that_fit() {
+        let mut prediction = Prediction::default();
+        let mut player = at(4);
+        let input = prediction.apply(&mut player, Direction::Right, &corridor());
+        assert_eq!(input.map(|input| input.sequence), Some(1));
+        assert_eq!(player.x, 6);
+
+        assert_eq!(
+            prediction.apply(&mut player, Direction::Right, &corridor()),
+            None
+        );
+        assert_eq!(player.x, 6);
+        let input = prediction.apply(&mut player, Direction::Left, &corridor());
+        assert_eq!(input.map(|input| input.sequence), Some(2));
+    }
+
+    #[test]
+    fn reconcile_replays_unprocessed_inputs() {
+        let mut prediction = Prediction::default();
+        let player = walk_right(&mut prediction, at(0), 3);
+        assert_eq!(player.x, 6);
+
+        // The server has applied the first input and agrees.
+        assert_eq!(prediction.reconcile(at(2), 1, &corridor()).x, 6);
+        // It refused the second after all; the third still stands.
+        assert_eq!(prediction.reconcile(at(2), 2, &corridor()).x, 4);
+        assert_eq!(prediction.reconcile(at(4), 3, &corridor()).x, 4);
+    }
+
+    #[test]
+    fn reject_replays_the_rest_from_the_last_snapshot() {
+        let mut prediction = Prediction::default();
+        assert_eq!(prediction.reject(1, &corridor()), None);
+
+        prediction.reconcile(at(0), 0, &corridor());
+        let player = walk_right(&mut prediction, at(0), 2);
+        assert_eq!(player.x, 4);
+        assert_eq!(prediction.reject(1, &corridor()).map(|p| p.x), Some(2));
+        assert_eq!(prediction.reject(2, &corridor()).map(|p| p.x), Some(0));
+    }
+
+    #[test]
+    fn reset_forgets_pending_inputs() {
+        let mut prediction = Prediction::default();
+        prediction.reconcile(at(0), 0, &corridor());
+        walk_right(&mut prediction, at(0), 2);
+        prediction.reset();
+        assert_eq!(prediction.reject(1, &corridor()), None);
+        assert_eq!(prediction.reconcile(at(0), 0, &corridor()).x, 0);
+    }
+}
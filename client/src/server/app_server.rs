@@ -12,8 +12,10 @@ use russh::{MethodKind, MethodSet, server::*};
 use tokio::sync::Mutex;
 use tokio::sync::mpsc::unbounded_channel;
 
+use roam_protocol::validate_name;
+
 use crate::app::{App, Event};
-use crate::player::Player;
+use crate::connection::Profile;
 use crate::server::terminal_handle::TerminalHandle;
 
 type SshTerminal = Terminal<CrosstermBackend<TerminalHandle>>;
@@ -22,12 +24,15 @@ type SshTerminal = Terminal<CrosstermBackend<TerminalHandle>>;
 pub struct AppServer {
     clients: Arc<Mutex<HashMap<usize, ClientData>>>,
     id: usize,
+    /// The SSH username, used as the player's name when it is a valid one.
+    username: Option<String>,
 }
 
 struct ClientData {
     terminal: Arc<Mutex<SshTerminal>>,
-    app: Arc<Mutex<App>>,
+    _app: Arc<Mutex<App>>,
     event_tx: tokio::sync::mpsc::UnboundedSender<Event>,
+    own_tx: tokio::sync::mpsc::UnboundedSender<Event>,
     last_activity: std::time::Instant,
     handle: Handle,
     channel_id: ChannelId,
@@ -35,11 +40,20 @@ struct ClientData {
     _app_handle: tokio::task::JoinHandle<()>,
 }
 
+impl ClientData {
+    /// Tells the game server this player left so their square disappears
+    /// right away instead of lingering until the server times them out.
+    fn disconnect(&self) {
+        let _ = self.own_tx.send(Event::Disconnect);
+    }
+}
+
 impl AppServer {
     pub fn new() -> Self {
         Self {
             clients: Arc::new(Mutex::new(HashMap::new())),
             id: 0,
+            username: None,
         }
     }
 
@@ -86,7 +100,9 @@ impl AppServer {
                         .data(channel_id, reset_sequence.as_ref().into())
                         .await;
                     let _ = handle.close(channel_id).await;
-                    clients_timeout.lock().await.remove(&id);
+                    if let Some(client_data) = clients_timeout.lock().await.remove(&id) {
+                        client_data.disconnect();
+                    }
                 }
             }
         });
@@ -131,6 +147,7 @@ impl AppServer {
             b"\x7f" => Some(KeyCode::Backspace),
             b"\x1b[3~" => Some(KeyCode::Delete),
             b"\r" | b"\n" => Some(KeyCode::Enter),
+            b"\x1b" => Some(KeyCode::Esc),
             b" " => Some(KeyCode::Char(' ')),
             [c] if c.is_ascii() && c.is_ascii_graphic() => Some(KeyCode::Char(*c as char)),
             _ => None,
@@ -179,21 +196,38 @@ impl Handler for AppServer {
         };
 
         let terminal = Arc::new(Mutex::new(Terminal::with_options(backend, options)?));
-        let app = App {
-            exit: false,
-            players: vec![Player { x: 0, y: 0 }],
-            own_player: Player { x: 0, y: 0 },
-        };
+        let app = App::new();
 
         // Create channels for this client
         let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel::<Event>();
         let (own_tx, own_rx) = tokio::sync::mpsc::unbounded_channel::<Event>();
 
+        // Remote players move between snapshots, so redraw on a timer too.
+        // The ticker only holds a weak sender so it does not keep the app
+        // loop alive once the session is gone.
+        let event_tx_render = event_tx.downgrade();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(crate::app::RENDER_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(tx) = event_tx_render.upgrade() else {
+                    break;
+                };
+                if tx.send(Event::Render).is_err() {
+                    break;
+                }
+            }
+        });
+
         // Spawn background connection
         let event_tx_bg = event_tx.clone();
         let background_handle = tokio::spawn(crate::app::run_background_connection_async(
             event_tx_bg,
             own_rx,
+            Profile {
+                name: self.username.clone(),
+                ..Profile::default()
+            },
         ));
 
         // App arc
@@ -229,8 +263,9 @@ impl Handler for AppServer {
             self.id,
             ClientData {
                 terminal,
-                app: app_arc,
+                _app: app_arc,
                 event_tx,
+                own_tx,
                 last_activity: std::time::Instant::now(),
                 handle,
                 channel_id,
@@ -242,7 +277,9 @@ impl Handler for AppServer {
         Ok(true)
     }
 
-    async fn auth_none(&mut self, _: &str) -> Result<Auth, Self::Error> {
+    async fn auth_none(&mut self, user: &str) -> Result<Auth, Self::Error> {
+        // Usernames the game server would refuse fall back to a generated name.
+        self.username = validate_name(user).is_ok().then(|| user.to_string());
         Ok(Auth::Accept)
     }
 
@@ -327,7 +364,9 @@ impl Handler for AppServer {
         let reset_sequence = b"\x1b[0m\x1b[2J\x1b[H\x1b[r\x1b[?25h";
         let _ = session.data(channel, reset_sequence.as_ref().into());
 
-        clients.remove(&self.id);
+        if let Some(client_data) = clients.remove(&self.id) {
+            client_data.disconnect();
+        }
         session.close(channel)?;
         Ok(())
     }
@@ -340,7 +379,9 @@ impl Drop for AppServer {
         // Note: Can't send reset sequence here since we don't have session access
         tokio::spawn(async move {
             let mut clients = clients.lock().await;
-            clients.remove(&id);
+            if let Some(client_data) = clients.remove(&id) {
+                client_data.disconnect();
+            }
         });
     }
 }
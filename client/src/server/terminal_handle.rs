@@ -22,12 +22,8 @@ impl std::io::Write for TerminalHandle {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let result = self.sender.send(self.sink.clone());
-        if result.is_err() {
-            return Err(io::Error::new(
-                io::ErrorKind::BrokenPipe,
-                result.unwrap_err(),
-            ));
+        if let Err(e) = self.sender.send(self.sink.clone()) {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, e));
         }
 
         self.sink.clear();
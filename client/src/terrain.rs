@@ -0,0 +1,37 @@
+use ratatui::{
+    prelude::{Buffer, Rect},
+    style::{Color, Style},
+    widgets::Widget,
+};
+use roam_protocol::{Map, Tile};
+
+use crate::camera::Camera;
+
+/// Draws the part of a [`Map`] the camera looks at, one cell per tile.
+pub struct Terrain<'a>(pub &'a Map, pub Camera);
+
+impl Widget for Terrain<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for position in area.positions() {
+            let Some(tile) = self
+                .1
+                .to_world(position)
+                .and_then(|(x, y)| self.0.tile(x, y))
+            else {
+                continue;
+            };
+            let (symbol, style) = appearance(tile);
+            buf[position].set_symbol(symbol).set_style(style);
+        }
+    }
+}
+
+fn appearance(tile: Tile) -> (&'static str, Style) {
+    match tile {
+        Tile::Floor => ("·", Style::new().fg(Color::DarkGray)),
+        Tile::Wall => (" ", Style::new().bg(Color::Gray)),
+        Tile::Water => ("~", Style::new().fg(Color::LightBlue).bg(Color::Blue)),
+        Tile::Door => ("+", Style::new().fg(Color::Yellow)),
+        Tile::Social => ("·", Style::new().fg(Color::Magenta)),
+    }
+}
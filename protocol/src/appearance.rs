@@ -0,0 +1,89 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::DecodeError;
+use crate::binary::{Binary, Reader, Writer};
+
+/// The palette players pick their colour from. Clients decide how each one
+/// looks in their terminal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerColor {
+    #[default]
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    Orange,
+    Pink,
+}
+
+impl PlayerColor {
+    pub const ALL: [PlayerColor; 8] = [
+        PlayerColor::Red,
+        PlayerColor::Green,
+        PlayerColor::Yellow,
+        PlayerColor::Blue,
+        PlayerColor::Magenta,
+        PlayerColor::Cyan,
+        PlayerColor::Orange,
+        PlayerColor::Pink,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PlayerColor::Red => "red",
+            PlayerColor::Green => "green",
+            PlayerColor::Yellow => "yellow",
+            PlayerColor::Blue => "blue",
+            PlayerColor::Magenta => "magenta",
+            PlayerColor::Cyan => "cyan",
+            PlayerColor::Orange => "orange",
+            PlayerColor::Pink => "pink",
+        }
+    }
+}
+
+impl fmt::Display for PlayerColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for PlayerColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PlayerColor::ALL
+            .into_iter()
+            .find(|color| color.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = PlayerColor::ALL.iter().map(|c| c.name()).collect();
+                format!("unknown color {s:?}, expected one of {}", names.join(", "))
+            })
+    }
+}
+
+impl Binary for PlayerColor {
+    fn write(&self, w: &mut Writer) {
+        // `ALL` lists every variant, so the position always exists.
+        let index = PlayerColor::ALL.iter().position(|c| c == self).unwrap_or(0);
+        w.byte(index as u8);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        PlayerColor::ALL
+            .get(usize::from(r.byte()?))
+            .copied()
+            .ok_or(DecodeError::InvalidValue("color"))
+    }
+}
+
+/// Whether `c` can stand in for a player on the map. Only single-cell ASCII
+/// symbols are allowed so every terminal draws them the same width.
+pub fn is_valid_glyph(c: char) -> bool {
+    c.is_ascii_graphic()
+}
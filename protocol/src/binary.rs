@@ -0,0 +1,224 @@
+//! Compact binary encoding.
+//!
+//! A binary datagram starts with [`BINARY_MAGIC`] followed by a one byte
+//! message tag and the message fields. Integers are LEB128 varints, so small
+//! coordinates take a single byte, and strings and lists are prefixed with
+//! their length.
+
+use crate::DecodeError;
+
+/// First byte of every binary datagram. It is not valid ASCII, so it can never
+/// be confused with the tag of a JSON frame.
+pub const BINARY_MAGIC: u8 = 0xB0;
+
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new(tag: u8) -> Self {
+        Self {
+            bytes: vec![BINARY_MAGIC, tag],
+        }
+    }
+
+    pub fn write<T: Binary>(&mut self, value: &T) -> &mut Self {
+        value.write(self);
+        self
+    }
+
+    /// Builder form of [`Writer::write`] for the top level of a message.
+    pub fn with<T: Binary>(mut self, value: &T) -> Self {
+        value.write(&mut self);
+        self
+    }
+
+    pub fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    pub fn byte(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Splits the magic byte and message tag off a binary datagram.
+    pub fn new(bytes: &'a [u8]) -> Result<(u8, Self), DecodeError> {
+        match bytes {
+            [BINARY_MAGIC, tag, rest @ ..] => Ok((*tag, Self { bytes: rest })),
+            _ => Err(DecodeError::UnexpectedEnd),
+        }
+    }
+
+    pub fn read<T: Binary>(&mut self) -> Result<T, DecodeError> {
+        T::read(self)
+    }
+
+    pub fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            // The tenth byte only has room for the top bit of a u64.
+            if shift == 63 && byte > 1 {
+                return Err(DecodeError::InvalidVarint);
+            }
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::InvalidVarint)
+    }
+
+    pub fn byte(&mut self) -> Result<u8, DecodeError> {
+        let (&byte, rest) = self.bytes.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.bytes.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    /// Fails if any bytes are left over after the last field.
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes)
+        }
+    }
+}
+
+/// Types that know how to write themselves in the binary encoding.
+pub trait Binary: Sized {
+    fn write(&self, w: &mut Writer);
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError>;
+}
+
+macro_rules! varint_impl {
+    ($($ty:ty),*) => {
+        $(
+            impl Binary for $ty {
+                fn write(&self, w: &mut Writer) {
+                    w.varint(u64::from(*self));
+                }
+
+                fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+                    <$ty>::try_from(r.varint()?).map_err(|_| DecodeError::InvalidVarint)
+                }
+            }
+        )*
+    };
+}
+
+varint_impl!(u16, u32, u64);
+
+impl Binary for u8 {
+    fn write(&self, w: &mut Writer) {
+        w.byte(*self);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        r.byte()
+    }
+}
+
+impl Binary for char {
+    fn write(&self, w: &mut Writer) {
+        w.varint(u64::from(u32::from(*self)));
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let value = u32::try_from(r.varint()?).map_err(|_| DecodeError::InvalidVarint)?;
+        char::from_u32(value).ok_or(DecodeError::InvalidValue("char"))
+    }
+}
+
+impl Binary for bool {
+    fn write(&self, w: &mut Writer) {
+        w.byte(u8::from(*self));
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match r.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue("bool")),
+        }
+    }
+}
+
+impl Binary for String {
+    fn write(&self, w: &mut Writer) {
+        w.varint(self.len() as u64);
+        w.bytes.extend_from_slice(self.as_bytes());
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let len = r.varint()? as usize;
+        let bytes = r.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Binary> Binary for Vec<T> {
+    fn write(&self, w: &mut Writer) {
+        w.varint(self.len() as u64);
+        for item in self {
+            item.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let len = r.varint()? as usize;
+        // Every item takes at least one byte, which stops a bogus length from
+        // reserving a huge allocation.
+        if len > r.bytes.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(T::read(r)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Binary> Binary for Option<T> {
+    fn write(&self, w: &mut Writer) {
+        match self {
+            Some(value) => {
+                w.byte(1);
+                value.write(w);
+            }
+            None => w.byte(0),
+        }
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match r.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::read(r)?)),
+            _ => Err(DecodeError::InvalidValue("option")),
+        }
+    }
+}
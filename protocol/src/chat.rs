@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use crate::binary::{Binary, Reader, Writer};
+use crate::{ChatError, DecodeError, PlayerId};
+
+/// Longest chat message, in characters.
+pub const MAX_CHAT_LENGTH: usize = 200;
+
+/// Checks the text of a chat message: 1 to [`MAX_CHAT_LENGTH`] characters,
+/// not all blank, and no control characters that could mess with other
+/// players' terminals.
+pub fn validate_chat(text: &str) -> Result<(), ChatError> {
+    if text.trim().is_empty() {
+        return Err(ChatError::Empty);
+    }
+    if text.chars().any(char::is_control) {
+        return Err(ChatError::ControlChar);
+    }
+    if text.chars().count() > MAX_CHAT_LENGTH {
+        return Err(ChatError::TooLong);
+    }
+    Ok(())
+}
+
+/// Who gets to hear a chat message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatScope {
+    /// Every player in the speaker's room.
+    #[default]
+    Everyone,
+    /// Only players close enough to the speaker, as decided by the server.
+    Nearby,
+}
+
+impl Binary for ChatScope {
+    fn write(&self, w: &mut Writer) {
+        w.byte(match self {
+            ChatScope::Everyone => 0,
+            ChatScope::Nearby => 1,
+        });
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match r.byte()? {
+            0 => Ok(ChatScope::Everyone),
+            1 => Ok(ChatScope::Nearby),
+            _ => Err(DecodeError::InvalidValue("chat scope")),
+        }
+    }
+}
+
+/// Something a client wants to say.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub text: String,
+    #[serde(default)]
+    pub scope: ChatScope,
+}
+
+impl Binary for ChatRequest {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.text).write(&self.scope);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            text: r.read()?,
+            scope: r.read()?,
+        })
+    }
+}
+
+/// A chat line the server relays to everyone in its scope, including the
+/// one who wrote it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub from: PlayerId,
+    /// The sender's name when they spoke, so the line still makes sense
+    /// after they leave.
+    pub name: String,
+    pub text: String,
+    pub scope: ChatScope,
+    /// When the server relayed it, in seconds since the Unix epoch.
+    pub sent_at: u64,
+}
+
+impl Binary for ChatMessage {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.from)
+            .write(&self.name)
+            .write(&self.text)
+            .write(&self.scope)
+            .write(&self.sent_at);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            from: r.read()?,
+            name: r.read()?,
+            text: r.read()?,
+            scope: r.read()?,
+            sent_at: r.read()?,
+        })
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+
+use crate::Map;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    InvalidUtf8,
+    UnknownTag(String),
+    InvalidPayload(serde_json::Error),
+    UnexpectedEnd,
+    TrailingBytes,
+    InvalidVarint,
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidUtf8 => write!(f, "message is not valid UTF-8"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown message tag {tag:?}"),
+            DecodeError::InvalidPayload(e) => write!(f, "invalid message payload: {e}"),
+            DecodeError::UnexpectedEnd => write!(f, "message ended unexpectedly"),
+            DecodeError::TrailingBytes => write!(f, "unexpected bytes after message"),
+            DecodeError::InvalidVarint => write!(f, "varint is out of range"),
+            DecodeError::InvalidValue(what) => write!(f, "invalid {what} value"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::InvalidPayload(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for DecodeError {
+    fn from(e: serde_json::Error) -> Self {
+        DecodeError::InvalidPayload(e)
+    }
+}
+
+/// Why a player name was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum NameError {
+    Empty,
+    TooLong,
+    InvalidChar(char),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Empty => write!(f, "name is empty"),
+            NameError::TooLong => write!(
+                f,
+                "name is longer than {} characters",
+                crate::MAX_NAME_LENGTH
+            ),
+            NameError::InvalidChar(c) => {
+                write!(f, "name contains {c:?}; use letters, digits, '-' and '_'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+/// Why a chat message was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChatError {
+    Empty,
+    TooLong,
+    ControlChar,
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::Empty => write!(f, "message is empty"),
+            ChatError::TooLong => write!(
+                f,
+                "message is longer than {} characters",
+                crate::MAX_CHAT_LENGTH
+            ),
+            ChatError::ControlChar => write!(f, "message contains control characters"),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+/// Why a map file could not be read.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseMapError {
+    Empty,
+    TooLarge,
+    UnknownTile {
+        line: usize,
+        column: usize,
+        tile: char,
+    },
+}
+
+impl fmt::Display for ParseMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseMapError::Empty => write!(f, "map is empty"),
+            ParseMapError::TooLarge => write!(
+                f,
+                "map is too large to send to clients, keep it within {} columns and {} tiles",
+                Map::MAX_WIDTH,
+                Map::MAX_TILES
+            ),
+            ParseMapError::UnknownTile { line, column, tile } => {
+                write!(f, "unknown tile {tile:?} at line {line}, column {column}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseMapError {}
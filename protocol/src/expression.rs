@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+use crate::binary::{Binary, Reader, Writer};
+use crate::{DecodeError, PlayerId};
+
+/// A short animation a player can play over their square.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Emote {
+    Wave,
+    Dance,
+    Exclaim,
+    Question,
+}
+
+impl Binary for Emote {
+    fn write(&self, w: &mut Writer) {
+        w.byte(match self {
+            Emote::Wave => 0,
+            Emote::Dance => 1,
+            Emote::Exclaim => 2,
+            Emote::Question => 3,
+        });
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match r.byte()? {
+            0 => Ok(Emote::Wave),
+            1 => Ok(Emote::Dance),
+            2 => Ok(Emote::Exclaim),
+            3 => Ok(Emote::Question),
+            _ => Err(DecodeError::InvalidValue("emote")),
+        }
+    }
+}
+
+/// An emote the server relays to everyone in the player's room, saying who
+/// played it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerEmote {
+    pub player: PlayerId,
+    pub emote: Emote,
+}
+
+impl Binary for PlayerEmote {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.player).write(&self.emote);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            player: r.read()?,
+            emote: r.read()?,
+        })
+    }
+}
+
+/// Whether a player is around, shown next to their name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Presence {
+    #[default]
+    Available,
+    Away,
+    Busy,
+}
+
+impl Binary for Presence {
+    fn write(&self, w: &mut Writer) {
+        w.byte(match self {
+            Presence::Available => 0,
+            Presence::Away => 1,
+            Presence::Busy => 2,
+        });
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match r.byte()? {
+            0 => Ok(Presence::Available),
+            1 => Ok(Presence::Away),
+            2 => Ok(Presence::Busy),
+            _ => Err(DecodeError::InvalidValue("presence")),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Human-readable JSON encoding, kept around for debugging.
+//!
+//! A JSON frame is an upper-case tag (letters and underscores), immediately
+//! followed by the JSON payload (if any) and a trailing newline, e.g.
+//! `PLAYERS[{"id":1,"x":0,"y":0}]\n`.
+
+use serde::Serialize;
+
+use crate::DecodeError;
+
+pub fn frame<T: Serialize>(tag: &str, payload: Option<&T>) -> Vec<u8> {
+    let mut bytes = tag.as_bytes().to_vec();
+    if let Some(payload) = payload {
+        // Serializing plain data structures into a Vec cannot fail.
+        serde_json::to_writer(&mut bytes, payload).expect("payload is serializable");
+    }
+    bytes.push(b'\n');
+    bytes
+}
+
+pub fn split_frame(bytes: &[u8]) -> Result<(&str, &str), DecodeError> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|_| DecodeError::InvalidUtf8)?
+        .trim();
+    let tag_end = text
+        .find(|c: char| !(c.is_ascii_uppercase() || c == '_'))
+        .unwrap_or(text.len());
+    Ok(text.split_at(tag_end))
+}
+
+/// Checks that a frame whose tag carries nothing has nothing after it.
+pub fn no_payload(payload: &str) -> Result<(), DecodeError> {
+    if payload.is_empty() {
+        Ok(())
+    } else {
+        Err(DecodeError::TrailingBytes)
+    }
+}
@@ -0,0 +1,164 @@
+//! Wire types shared by the roam server and client.
+//!
+//! Every datagram exchanged between the two binaries is either a
+//! [`ClientMessage`] or a [`ServerMessage`]. Both sides go through the
+//! `encode`/`decode` functions defined here so the framing only lives in one
+//! place.
+//!
+//! Messages can be written in two [`Encoding`]s: a compact binary format used
+//! by default and a JSON format that is easier to read when debugging. The
+//! encoding is agreed on in the `CONNECT`/`WELCOME` handshake, and decoding
+//! accepts either.
+
+mod appearance;
+mod binary;
+mod chat;
+mod error;
+mod expression;
+mod json;
+mod map;
+mod message;
+mod movement;
+mod snapshot;
+
+pub use appearance::{PlayerColor, is_valid_glyph};
+pub use chat::{ChatMessage, ChatRequest, ChatScope, MAX_CHAT_LENGTH, validate_chat};
+pub use error::{ChatError, DecodeError, NameError, ParseMapError};
+pub use expression::{Emote, PlayerEmote, Presence};
+pub use map::{Map, Tile};
+pub use message::{ClientMessage, Connect, MapChunk, ServerMessage, Welcome};
+pub use movement::{Direction, Input, overlaps};
+pub use snapshot::{PlayerSet, Snapshot};
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use binary::{Binary, Reader, Writer};
+
+/// Bumped whenever the wire format changes incompatibly.
+pub const PROTOCOL_VERSION: u16 = 19;
+
+/// Largest payload that fits in a single UDP datagram.
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Json,
+    #[default]
+    Binary,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Encoding::Json),
+            "binary" => Ok(Encoding::Binary),
+            _ => Err(format!("unknown encoding {s:?}, expected json or binary")),
+        }
+    }
+}
+
+impl Binary for Encoding {
+    fn write(&self, w: &mut Writer) {
+        w.byte(match self {
+            Encoding::Json => 0,
+            Encoding::Binary => 1,
+        });
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match r.byte()? {
+            0 => Ok(Encoding::Json),
+            1 => Ok(Encoding::Binary),
+            _ => Err(DecodeError::InvalidValue("encoding")),
+        }
+    }
+}
+
+/// Identifier the server assigns to a player on `CONNECT`. It stays the same
+/// for as long as the player is connected, so clients can tell players apart
+/// between broadcasts.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct PlayerId(pub u32);
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+impl Binary for PlayerId {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.0);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(PlayerId(r.read()?))
+    }
+}
+
+/// Longest display name, in characters.
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// Checks a display name: 1 to [`MAX_NAME_LENGTH`] ASCII letters, digits,
+/// `-` or `_`, so it is short enough for a name tag and renders the same in
+/// every terminal.
+pub fn validate_name(name: &str) -> Result<(), NameError> {
+    if name.is_empty() {
+        return Err(NameError::Empty);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        return Err(NameError::InvalidChar(c));
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(NameError::TooLong);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Player {
+    pub id: PlayerId,
+    pub name: String,
+    pub color: PlayerColor,
+    /// Drawn on the player's square instead of leaving it blank.
+    pub glyph: Option<char>,
+    #[serde(default)]
+    pub presence: Presence,
+    pub x: u16,
+    pub y: u16,
+}
+
+impl Binary for Player {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.id)
+            .write(&self.name)
+            .write(&self.color)
+            .write(&self.glyph)
+            .write(&self.presence)
+            .write(&self.x)
+            .write(&self.y);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            id: r.read()?,
+            name: r.read()?,
+            color: r.read()?,
+            glyph: r.read()?,
+            presence: r.read()?,
+            x: r.read()?,
+            y: r.read()?,
+        })
+    }
+}
@@ -0,0 +1,348 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::binary::{Binary, Reader, Writer};
+use crate::{DecodeError, ParseMapError};
+
+/// Most bytes of tiles to put in one [`MapChunk`](crate::MapChunk), leaving
+/// room for the rest of the datagram within a typical MTU.
+const CHUNK_BYTES: usize = 1024;
+
+/// Bytes JSON adds around each row: its quotes and the comma after it.
+/// Binary rows are smaller, one byte per tile.
+const ROW_OVERHEAD: usize = 3;
+
+/// One cell of the world.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tile {
+    Floor,
+    Wall,
+    Water,
+    Door,
+    /// Floor where players may stand on top of each other, e.g. around a
+    /// meeting point.
+    Social,
+}
+
+impl Tile {
+    /// Whether players can stand on this tile.
+    pub fn is_walkable(self) -> bool {
+        matches!(self, Tile::Floor | Tile::Door | Tile::Social)
+    }
+
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '.' | ' ' => Some(Tile::Floor),
+            '#' => Some(Tile::Wall),
+            '~' => Some(Tile::Water),
+            '+' => Some(Tile::Door),
+            ':' => Some(Tile::Social),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            Tile::Floor => '.',
+            Tile::Wall => '#',
+            Tile::Water => '~',
+            Tile::Door => '+',
+            Tile::Social => ':',
+        }
+    }
+}
+
+impl Binary for Tile {
+    fn write(&self, w: &mut Writer) {
+        w.byte(match self {
+            Tile::Floor => 0,
+            Tile::Wall => 1,
+            Tile::Water => 2,
+            Tile::Door => 3,
+            Tile::Social => 4,
+        });
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match r.byte()? {
+            0 => Ok(Tile::Floor),
+            1 => Ok(Tile::Wall),
+            2 => Ok(Tile::Water),
+            3 => Ok(Tile::Door),
+            4 => Ok(Tile::Social),
+            _ => Err(DecodeError::InvalidValue("tile")),
+        }
+    }
+}
+
+/// The tiles making up the world, row by row.
+///
+/// Maps are written as text, one character per tile: `.` floor, `#` wall,
+/// `~` water, `+` door and `:` social floor. Short lines are padded with
+/// walls. In JSON a map is the list of those lines.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "Vec<String>", try_from = "Vec<String>")]
+pub struct Map {
+    width: u16,
+    height: u16,
+    tiles: Vec<Tile>,
+}
+
+impl Map {
+    /// Most tiles a map may have, which bounds how many chunks a client is
+    /// sent on entering a room.
+    pub const MAX_TILES: usize = 64 * CHUNK_BYTES;
+
+    /// Widest a map may be, so that a single row fits in a chunk.
+    pub const MAX_WIDTH: usize = CHUNK_BYTES - ROW_OVERHEAD;
+
+    /// A map of the given size made of nothing but walls, to be filled in
+    /// with [`Map::paste`].
+    pub fn walls(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![Tile::Wall; usize::from(width) * usize::from(height)],
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// The tile at `(x, y)`, or `None` outside the map.
+    pub fn tile(&self, x: u16, y: u16) -> Option<Tile> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.tiles
+            .get(usize::from(y) * usize::from(self.width) + usize::from(x))
+            .copied()
+    }
+
+    /// Whether a two cell wide player fits at `(x, y)`.
+    pub fn fits(&self, x: u16, y: u16) -> bool {
+        let walkable = |x, y| self.tile(x, y).is_some_and(Tile::is_walkable);
+        walkable(x, y) && x.checked_add(1).is_some_and(|right| walkable(right, y))
+    }
+
+    /// Whether a player at `(x, y)` stands on social floor and may overlap
+    /// others.
+    pub fn is_social(&self, x: u16, y: u16) -> bool {
+        self.tile(x, y) == Some(Tile::Social)
+            || self.tile(x.saturating_add(1), y) == Some(Tile::Social)
+    }
+
+    /// Spots where a player can be placed, reading row by row. Players move
+    /// two columns at a time, so these are always on even columns to line up
+    /// with doors drawn there.
+    pub fn spawn_points(&self) -> impl Iterator<Item = (u16, u16)> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).step_by(2).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.fits(x, y))
+    }
+
+    /// The map cut into bands of whole rows, each small enough to send in a
+    /// chunk of its own, along with the row each band starts at.
+    pub fn chunks(&self) -> impl Iterator<Item = (u16, Map)> + '_ {
+        let width = usize::from(self.width).max(1);
+        let rows = (CHUNK_BYTES / (width + ROW_OVERHEAD)).max(1);
+        self.tiles
+            .chunks(rows * width)
+            .enumerate()
+            .map(move |(index, tiles)| {
+                let band = Map {
+                    width: self.width,
+                    height: (tiles.len() / width) as u16,
+                    tiles: tiles.to_vec(),
+                };
+                ((index * rows) as u16, band)
+            })
+    }
+
+    /// Copies the rows of `band` over this map's, starting at `row`. Returns
+    /// false, changing nothing, when the band is a different width or runs
+    /// past the bottom.
+    pub fn paste(&mut self, row: u16, band: &Map) -> bool {
+        let end = usize::from(row) + usize::from(band.height);
+        if band.width != self.width || end > usize::from(self.height) {
+            return false;
+        }
+        let start = usize::from(row) * usize::from(self.width);
+        self.tiles[start..start + band.tiles.len()].copy_from_slice(&band.tiles);
+        true
+    }
+
+    fn rows(&self) -> impl Iterator<Item = &[Tile]> {
+        self.tiles.chunks(usize::from(self.width).max(1))
+    }
+}
+
+impl FromStr for Map {
+    type Err = ParseMapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Spaces are floor, so only line breaks are trimmed.
+        let lines: Vec<&str> = s.trim_end_matches(['\n', '\r']).lines().collect();
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        if width == 0 {
+            return Err(ParseMapError::Empty);
+        }
+        if width > Map::MAX_WIDTH || width * lines.len() > Map::MAX_TILES {
+            return Err(ParseMapError::TooLarge);
+        }
+        let height = u16::try_from(lines.len()).map_err(|_| ParseMapError::TooLarge)?;
+
+        let mut tiles = Vec::with_capacity(width * lines.len());
+        for (row, line) in lines.iter().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                let tile = Tile::from_char(c).ok_or(ParseMapError::UnknownTile {
+                    line: row + 1,
+                    column: column + 1,
+                    tile: c,
+                })?;
+                tiles.push(tile);
+            }
+            tiles.resize((row + 1) * width, Tile::Wall);
+        }
+        Ok(Self {
+            width: width as u16,
+            height,
+            tiles,
+        })
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.rows() {
+            let line: String = row.iter().map(|tile| tile.to_char()).collect();
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Map> for Vec<String> {
+    fn from(map: Map) -> Self {
+        map.rows()
+            .map(|row| row.iter().map(|tile| tile.to_char()).collect())
+            .collect()
+    }
+}
+
+impl TryFrom<Vec<String>> for Map {
+    type Error = ParseMapError;
+
+    fn try_from(rows: Vec<String>) -> Result<Self, Self::Error> {
+        rows.join("\n").parse()
+    }
+}
+
+impl Binary for Map {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.width).write(&self.height).write(&self.tiles);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let map = Self {
+            width: r.read()?,
+            height: r.read()?,
+            tiles: r.read()?,
+        };
+        if map.tiles.len() != usize::from(map.width) * usize::from(map.height) {
+            return Err(DecodeError::InvalidValue("map"));
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Encoding, MapChunk, ServerMessage};
+
+    #[test]
+    fn trailing_spaces_are_floor() {
+        let map: Map = "#   \n#..#\r\n".parse().unwrap();
+        assert_eq!(map.to_string(), "#...\n#..#\n");
+    }
+
+    #[test]
+    fn short_lines_are_walled_in() {
+        let map: Map = "#...\n#.".parse().unwrap();
+        assert_eq!(map.to_string(), "#...\n#.##\n");
+    }
+
+    /// A `width` by `height` map using every kind of tile.
+    fn patterned(width: usize, height: usize) -> Map {
+        let tiles = ['.', '#', '~', '+', ':'];
+        let text: String = (0..height)
+            .map(|y| {
+                let mut line: String = (0..width).map(|x| tiles[(x * 7 + y) % 5]).collect();
+                line.push('\n');
+                line
+            })
+            .collect();
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn chunks_paste_back_into_the_map() {
+        let sizes = [
+            (1, 1),
+            (3, 500),
+            (40, 30),
+            (Map::MAX_WIDTH, Map::MAX_TILES / Map::MAX_WIDTH),
+            (256, Map::MAX_TILES / 256),
+        ];
+        for (width, height) in sizes {
+            let map = patterned(width, height);
+            let mut copy = Map::walls(map.width(), map.height());
+            let mut next_row = 0;
+            for (row, band) in map.chunks() {
+                assert_eq!(row, next_row, "{width}x{height}");
+                next_row += band.height();
+                for encoding in [Encoding::Json, Encoding::Binary] {
+                    let chunk = ServerMessage::MapChunk(MapChunk {
+                        room: u16::MAX,
+                        row,
+                        tiles: band.clone(),
+                    });
+                    // Well within the smallest MTU IPv6 allows, after headers.
+                    let size = chunk.encode(encoding).len();
+                    assert!(size <= 1200, "{width}x{height} chunk is {size} bytes");
+                }
+                assert!(copy.paste(row, &band));
+            }
+            assert_eq!(usize::from(next_row), height);
+            assert_eq!(copy, map);
+        }
+    }
+
+    #[test]
+    fn paste_refuses_bands_that_do_not_fit() {
+        let mut map = Map::walls(4, 3);
+        let band = patterned(4, 2);
+        assert!(!map.paste(2, &band));
+        assert!(!map.paste(0, &patterned(6, 1)));
+        assert_eq!(map, Map::walls(4, 3));
+        assert!(map.paste(1, &band));
+        assert_eq!(map.to_string(), format!("####\n{band}"));
+    }
+
+    #[test]
+    fn too_many_rows_are_too_large() {
+        let text = ".\n".repeat(usize::from(u16::MAX) + 1);
+        assert_eq!(text.parse::<Map>(), Err(ParseMapError::TooLarge));
+    }
+}
@@ -0,0 +1,577 @@
+use serde::{Deserialize, Serialize};
+
+use crate::binary::{BINARY_MAGIC, Binary, Reader, Writer};
+use crate::json::{frame, no_payload, split_frame};
+use crate::{
+    ChatMessage, ChatRequest, DecodeError, Emote, Encoding, Input, Map, PROTOCOL_VERSION, Player,
+    PlayerColor, PlayerEmote, PlayerId, Presence, Snapshot,
+};
+
+/// Messages sent from a client to the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientMessage {
+    Connect(Connect),
+    /// Asks the server to move our player one step.
+    Move(Input),
+    Disconnect,
+    /// Acknowledges the snapshot with this sequence number, making it the
+    /// baseline for the next delta.
+    Ack(u32),
+    /// Keeps an idle connection alive; the server answers with its own.
+    Heartbeat,
+    /// Something to say to everyone, or just to those nearby. Like moves,
+    /// chat is sent once and lost if the datagram is.
+    Chat(ChatRequest),
+    /// Plays an emote over our player for everyone to see.
+    Emote(Emote),
+    /// Marks us as available, away or busy until changed again.
+    SetPresence(Presence),
+    /// Echoes the resume token from a [`Welcome`], showing the server that
+    /// we really are at the address the `CONNECT` came from. Until it hears
+    /// this or an [`Ack`](ClientMessage::Ack), the server sends the address
+    /// nothing but the `WELCOME`.
+    Confirm(u64),
+}
+
+/// Messages sent from the server to a client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerMessage {
+    Welcome(Welcome),
+    Snapshot(Snapshot),
+    /// A player disconnected; sent straight away rather than waiting for the
+    /// next snapshot.
+    PlayerLeft(PlayerId),
+    Heartbeat,
+    Error(String),
+    /// The input with this sequence number was not applied, because it would
+    /// have run into something or the client is moving too fast.
+    MoveRejected(u32),
+    Chat(ChatMessage),
+    Emote(PlayerEmote),
+    MapChunk(MapChunk),
+}
+
+/// Opens the handshake. Clients always send this as JSON so that any server
+/// can read it, whatever encoding the client asks for.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Connect {
+    pub version: u16,
+    pub encoding: Encoding,
+    /// Token from an earlier [`Welcome`], asking the server to hand back the
+    /// same player instead of spawning a new one.
+    #[serde(default)]
+    pub resume: Option<u64>,
+    /// The name to show for this player. Empty lets the server pick one.
+    #[serde(default)]
+    pub name: String,
+    /// Preferred colour; the server assigns one if this is missing.
+    #[serde(default)]
+    pub color: Option<PlayerColor>,
+    #[serde(default)]
+    pub glyph: Option<char>,
+}
+
+impl Default for Connect {
+    /// A bare `CONNECT` is treated as a current-version client that wants
+    /// JSON, which keeps the server easy to poke at by hand.
+    fn default() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            encoding: Encoding::Json,
+            resume: None,
+            name: String::new(),
+            color: None,
+            glyph: None,
+        }
+    }
+}
+
+impl Binary for Connect {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.version)
+            .write(&self.encoding)
+            .write(&self.resume)
+            .write(&self.name)
+            .write(&self.color)
+            .write(&self.glyph);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            version: r.read()?,
+            encoding: r.read()?,
+            resume: r.read()?,
+            name: r.read()?,
+            color: r.read()?,
+            glyph: r.read()?,
+        })
+    }
+}
+
+/// Reply to `CONNECT` telling the client which player it controls, where that
+/// player is, the room it is in, and which encoding the rest of the session
+/// uses.
+///
+/// The server sends another one whenever the player goes through a door into
+/// a different room. The room's map is too big to share the datagram, so it
+/// follows in [`MapChunk`]s, on a new connection only once the client has
+/// answered with [`ClientMessage::Confirm`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Welcome {
+    pub player: Player,
+    /// Identifies the room within this server; see [`Snapshot::room`].
+    pub room: u16,
+    pub room_name: String,
+    pub map_width: u16,
+    pub map_height: u16,
+    /// Ticks per second, which tells how far apart [`Snapshot::tick`]s are.
+    pub tick_rate: u32,
+    pub encoding: Encoding,
+    /// Pass this back in [`Connect::resume`] to get the same player again
+    /// after losing the connection.
+    pub resume_token: u64,
+}
+
+impl Binary for Welcome {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.player)
+            .write(&self.room)
+            .write(&self.room_name)
+            .write(&self.map_width)
+            .write(&self.map_height)
+            .write(&self.tick_rate)
+            .write(&self.encoding)
+            .write(&self.resume_token);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            player: r.read()?,
+            room: r.read()?,
+            room_name: r.read()?,
+            map_width: r.read()?,
+            map_height: r.read()?,
+            tick_rate: r.read()?,
+            encoding: r.read()?,
+            resume_token: r.read()?,
+        })
+    }
+}
+
+/// Some whole rows of the map of the room a [`Welcome`] was for, starting at
+/// `row`. Each chunk is small enough to travel in an unfragmented datagram.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapChunk {
+    pub room: u16,
+    pub row: u16,
+    pub tiles: Map,
+}
+
+impl Binary for MapChunk {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.room).write(&self.row).write(&self.tiles);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            room: r.read()?,
+            row: r.read()?,
+            tiles: r.read()?,
+        })
+    }
+}
+
+impl ClientMessage {
+    pub fn encode(&self, encoding: Encoding) -> Vec<u8> {
+        match encoding {
+            Encoding::Json => self.encode_json(),
+            Encoding::Binary => self.encode_binary(),
+        }
+    }
+
+    /// Decodes a datagram in either encoding.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.first() == Some(&BINARY_MAGIC) {
+            Self::decode_binary(bytes)
+        } else {
+            Self::decode_json(bytes)
+        }
+    }
+
+    fn encode_json(&self) -> Vec<u8> {
+        match self {
+            ClientMessage::Connect(connect) => frame("CONNECT", Some(connect)),
+            ClientMessage::Move(input) => frame("MOVE", Some(input)),
+            ClientMessage::Disconnect => frame("DISCONNECT", None::<&()>),
+            ClientMessage::Ack(sequence) => frame("ACK", Some(sequence)),
+            ClientMessage::Heartbeat => frame("HEARTBEAT", None::<&()>),
+            ClientMessage::Chat(request) => frame("CHAT", Some(request)),
+            ClientMessage::Emote(emote) => frame("EMOTE", Some(emote)),
+            ClientMessage::SetPresence(presence) => frame("SET_PRESENCE", Some(presence)),
+            ClientMessage::Confirm(token) => frame("CONFIRM", Some(token)),
+        }
+    }
+
+    fn decode_json(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (tag, payload) = split_frame(bytes)?;
+        match tag {
+            "CONNECT" if payload.is_empty() => Ok(ClientMessage::Connect(Connect::default())),
+            "CONNECT" => Ok(ClientMessage::Connect(serde_json::from_str(payload)?)),
+            "MOVE" => Ok(ClientMessage::Move(serde_json::from_str(payload)?)),
+            "DISCONNECT" => no_payload(payload).map(|()| ClientMessage::Disconnect),
+            "ACK" => Ok(ClientMessage::Ack(serde_json::from_str(payload)?)),
+            "HEARTBEAT" => no_payload(payload).map(|()| ClientMessage::Heartbeat),
+            "CHAT" => Ok(ClientMessage::Chat(serde_json::from_str(payload)?)),
+            "EMOTE" => Ok(ClientMessage::Emote(serde_json::from_str(payload)?)),
+            "SET_PRESENCE" => Ok(ClientMessage::SetPresence(serde_json::from_str(payload)?)),
+            "CONFIRM" => Ok(ClientMessage::Confirm(serde_json::from_str(payload)?)),
+            _ => Err(DecodeError::UnknownTag(tag.to_string())),
+        }
+    }
+
+    fn encode_binary(&self) -> Vec<u8> {
+        let w = match self {
+            ClientMessage::Connect(connect) => Writer::new(0x01).with(connect),
+            ClientMessage::Move(input) => Writer::new(0x02).with(input),
+            ClientMessage::Disconnect => Writer::new(0x03),
+            ClientMessage::Ack(sequence) => Writer::new(0x04).with(sequence),
+            ClientMessage::Heartbeat => Writer::new(0x05),
+            ClientMessage::Chat(request) => Writer::new(0x06).with(request),
+            ClientMessage::Emote(emote) => Writer::new(0x07).with(emote),
+            ClientMessage::SetPresence(presence) => Writer::new(0x08).with(presence),
+            ClientMessage::Confirm(token) => Writer::new(0x09).with(token),
+        };
+        w.finish()
+    }
+
+    fn decode_binary(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (tag, mut r) = Reader::new(bytes)?;
+        let message = match tag {
+            0x01 => ClientMessage::Connect(r.read()?),
+            0x02 => ClientMessage::Move(r.read()?),
+            0x03 => ClientMessage::Disconnect,
+            0x04 => ClientMessage::Ack(r.read()?),
+            0x05 => ClientMessage::Heartbeat,
+            0x06 => ClientMessage::Chat(r.read()?),
+            0x07 => ClientMessage::Emote(r.read()?),
+            0x08 => ClientMessage::SetPresence(r.read()?),
+            0x09 => ClientMessage::Confirm(r.read()?),
+            _ => return Err(DecodeError::UnknownTag(format!("{tag:#04x}"))),
+        };
+        r.finish()?;
+        Ok(message)
+    }
+}
+
+impl ServerMessage {
+    pub fn encode(&self, encoding: Encoding) -> Vec<u8> {
+        match encoding {
+            Encoding::Json => self.encode_json(),
+            Encoding::Binary => self.encode_binary(),
+        }
+    }
+
+    /// Decodes a datagram in either encoding.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.first() == Some(&BINARY_MAGIC) {
+            Self::decode_binary(bytes)
+        } else {
+            Self::decode_json(bytes)
+        }
+    }
+
+    fn encode_json(&self) -> Vec<u8> {
+        match self {
+            ServerMessage::Welcome(welcome) => frame("WELCOME", Some(welcome)),
+            ServerMessage::Snapshot(snapshot) => frame("SNAPSHOT", Some(snapshot)),
+            ServerMessage::PlayerLeft(id) => frame("PLAYER_LEFT", Some(id)),
+            ServerMessage::Heartbeat => frame("HEARTBEAT", None::<&()>),
+            ServerMessage::Error(reason) => frame("ERROR", Some(reason)),
+            ServerMessage::MoveRejected(sequence) => frame("MOVE_REJECTED", Some(sequence)),
+            ServerMessage::Chat(message) => frame("CHAT", Some(message)),
+            ServerMessage::Emote(emote) => frame("EMOTE", Some(emote)),
+            ServerMessage::MapChunk(chunk) => frame("MAP_CHUNK", Some(chunk)),
+        }
+    }
+
+    fn decode_json(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (tag, payload) = split_frame(bytes)?;
+        match tag {
+            "WELCOME" => Ok(ServerMessage::Welcome(serde_json::from_str(payload)?)),
+            "SNAPSHOT" => Ok(ServerMessage::Snapshot(serde_json::from_str(payload)?)),
+            "PLAYER_LEFT" => Ok(ServerMessage::PlayerLeft(serde_json::from_str(payload)?)),
+            "HEARTBEAT" => no_payload(payload).map(|()| ServerMessage::Heartbeat),
+            "ERROR" => Ok(ServerMessage::Error(serde_json::from_str(payload)?)),
+            "MOVE_REJECTED" => Ok(ServerMessage::MoveRejected(serde_json::from_str(payload)?)),
+            "CHAT" => Ok(ServerMessage::Chat(serde_json::from_str(payload)?)),
+            "EMOTE" => Ok(ServerMessage::Emote(serde_json::from_str(payload)?)),
+            "MAP_CHUNK" => Ok(ServerMessage::MapChunk(serde_json::from_str(payload)?)),
+            _ => Err(DecodeError::UnknownTag(tag.to_string())),
+        }
+    }
+
+    fn encode_binary(&self) -> Vec<u8> {
+        let w = match self {
+            ServerMessage::Welcome(welcome) => Writer::new(0x01).with(welcome),
+            ServerMessage::Snapshot(snapshot) => Writer::new(0x02).with(snapshot),
+            ServerMessage::Error(reason) => Writer::new(0x03).with(reason),
+            ServerMessage::PlayerLeft(id) => Writer::new(0x04).with(id),
+            ServerMessage::Heartbeat => Writer::new(0x05),
+            ServerMessage::MoveRejected(sequence) => Writer::new(0x06).with(sequence),
+            ServerMessage::Chat(message) => Writer::new(0x07).with(message),
+            ServerMessage::Emote(emote) => Writer::new(0x08).with(emote),
+            ServerMessage::MapChunk(chunk) => Writer::new(0x09).with(chunk),
+        };
+        w.finish()
+    }
+
+    fn decode_binary(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (tag, mut r) = Reader::new(bytes)?;
+        let message = match tag {
+            0x01 => ServerMessage::Welcome(r.read()?),
+            0x02 => ServerMessage::Snapshot(r.read()?),
+            0x03 => ServerMessage::Error(r.read()?),
+            0x04 => ServerMessage::PlayerLeft(r.read()?),
+            0x05 => ServerMessage::Heartbeat,
+            0x06 => ServerMessage::MoveRejected(r.read()?),
+            0x07 => ServerMessage::Chat(r.read()?),
+            0x08 => ServerMessage::Emote(r.read()?),
+            0x09 => ServerMessage::MapChunk(r.read()?),
+            _ => return Err(DecodeError::UnknownTag(format!("{tag:#04x}"))),
+        };
+        r.finish()?;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatScope, Direction};
+
+    const ENCODINGS: [Encoding; 2] = [Encoding::Json, Encoding::Binary];
+
+    fn player() -> Player {
+        Player {
+            id: PlayerId(7),
+            name: "ada".to_string(),
+            color: PlayerColor::Cyan,
+            glyph: Some('@'),
+            presence: Presence::Away,
+            x: 300,
+            y: 12,
+        }
+    }
+
+    /// One of every message a client sends, with fields set to something
+    /// other than their defaults where possible.
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Connect(Connect {
+                version: PROTOCOL_VERSION,
+                encoding: Encoding::Binary,
+                resume: Some(u64::MAX),
+                name: "ada".to_string(),
+                color: Some(PlayerColor::Pink),
+                glyph: Some('é'),
+            }),
+            ClientMessage::Connect(Connect::default()),
+            ClientMessage::Move(Input {
+                sequence: 129,
+                direction: Direction::Left,
+            }),
+            ClientMessage::Disconnect,
+            ClientMessage::Ack(u32::MAX),
+            ClientMessage::Heartbeat,
+            ClientMessage::Chat(ChatRequest {
+                text: "hi \"there\"\n".to_string(),
+                scope: ChatScope::Nearby,
+            }),
+            ClientMessage::Emote(Emote::Question),
+            ClientMessage::SetPresence(Presence::Busy),
+            ClientMessage::Confirm(0xfedc_ba98_7654_3210),
+        ]
+    }
+
+    /// One of every message the server sends.
+    fn server_messages() -> Vec<ServerMessage> {
+        vec![
+            ServerMessage::Welcome(Welcome {
+                player: player(),
+                room: 2,
+                room_name: "garden".to_string(),
+                map_width: 160,
+                map_height: 48,
+                tick_rate: 30,
+                encoding: Encoding::Binary,
+                resume_token: 0x0123_4567_89ab_cdef,
+            }),
+            ServerMessage::Snapshot(Snapshot {
+                sequence: 1000,
+                room: 2,
+                tick: 70_000,
+                baseline: Some(998),
+                input: 5,
+                players: vec![player()],
+                removed: vec![PlayerId(3), PlayerId(4)],
+            }),
+            ServerMessage::Snapshot(Snapshot::default()),
+            ServerMessage::PlayerLeft(PlayerId(9)),
+            ServerMessage::Heartbeat,
+            ServerMessage::Error("server is full".to_string()),
+            ServerMessage::MoveRejected(17),
+            ServerMessage::Chat(ChatMessage {
+                from: PlayerId(7),
+                name: "ada".to_string(),
+                text: "hello".to_string(),
+                scope: ChatScope::Everyone,
+                sent_at: 1_700_000_000,
+            }),
+            ServerMessage::Emote(PlayerEmote {
+                player: PlayerId(7),
+                emote: Emote::Dance,
+            }),
+            ServerMessage::MapChunk(MapChunk {
+                room: 1,
+                row: 40,
+                tiles: "#.~\n+:#".parse().unwrap(),
+            }),
+        ]
+    }
+
+    /// Encodes each of `messages` with `encode`.
+    fn frames<T>(messages: Vec<T>, encode: impl Fn(&T) -> Vec<u8>) -> Vec<Vec<u8>> {
+        messages.iter().map(encode).collect()
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+        for encoding in ENCODINGS {
+            for message in client_messages() {
+                let decoded = ClientMessage::decode(&message.encode(encoding));
+                assert_eq!(decoded.unwrap(), message, "{encoding:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn server_messages_round_trip() {
+        for encoding in ENCODINGS {
+            for message in server_messages() {
+                let decoded = ServerMessage::decode(&message.encode(encoding));
+                assert_eq!(decoded.unwrap(), message, "{encoding:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_binary_is_rejected() {
+        let client = frames(client_messages(), |m| m.encode(Encoding::Binary));
+        for frame in &client {
+            for end in 0..frame.len() {
+                let decoded = ClientMessage::decode(&frame[..end]);
+                assert!(
+                    decoded.is_err(),
+                    "{:?} decoded as {decoded:?}",
+                    &frame[..end]
+                );
+            }
+        }
+        let server = frames(server_messages(), |m| m.encode(Encoding::Binary));
+        for frame in &server {
+            for end in 0..frame.len() {
+                let decoded = ServerMessage::decode(&frame[..end]);
+                assert!(
+                    decoded.is_err(),
+                    "{:?} decoded as {decoded:?}",
+                    &frame[..end]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_varint_is_rejected() {
+        let largest = ClientMessage::Confirm(u64::MAX).encode(Encoding::Binary);
+        assert_eq!(
+            largest[2..],
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+        assert_eq!(
+            ClientMessage::decode(&largest).unwrap(),
+            ClientMessage::Confirm(u64::MAX)
+        );
+
+        for last in [0x02, 0x7f, 0x81] {
+            let mut frame = largest.clone();
+            *frame.last_mut().unwrap() = last;
+            frame.push(0x00);
+            assert!(matches!(
+                ClientMessage::decode(&frame),
+                Err(DecodeError::InvalidVarint)
+            ));
+        }
+    }
+
+    #[test]
+    fn trailing_binary_is_rejected() {
+        for mut frame in frames(client_messages(), |m| m.encode(Encoding::Binary)) {
+            frame.push(0);
+            let decoded = ClientMessage::decode(&frame);
+            assert!(
+                matches!(decoded, Err(DecodeError::TrailingBytes)),
+                "{decoded:?}"
+            );
+        }
+        for mut frame in frames(server_messages(), |m| m.encode(Encoding::Binary)) {
+            frame.push(0);
+            let decoded = ServerMessage::decode(&frame);
+            assert!(
+                matches!(decoded, Err(DecodeError::TrailingBytes)),
+                "{decoded:?}"
+            );
+        }
+    }
+
+    /// JSON frames can only be caught short where the payload has a closing
+    /// bracket or quote: a number cut short is still a number.
+    #[test]
+    fn truncated_json_is_rejected() {
+        let cut = |frame: Vec<u8>| {
+            let text = String::from_utf8(frame).unwrap();
+            let text = text.trim_end();
+            text.ends_with(['}', ']', '"'])
+                .then(|| text[..text.len() - 1].to_string())
+        };
+        for frame in frames(client_messages(), |m| m.encode(Encoding::Json)) {
+            if let Some(text) = cut(frame) {
+                let decoded = ClientMessage::decode(text.as_bytes());
+                assert!(decoded.is_err(), "{text:?} decoded as {decoded:?}");
+            }
+        }
+        for frame in frames(server_messages(), |m| m.encode(Encoding::Json)) {
+            if let Some(text) = cut(frame) {
+                let decoded = ServerMessage::decode(text.as_bytes());
+                assert!(decoded.is_err(), "{text:?} decoded as {decoded:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_json_is_rejected() {
+        let extend = |frame: Vec<u8>| {
+            let text = String::from_utf8(frame).unwrap();
+            format!("{}x\n", text.trim_end())
+        };
+        for frame in frames(client_messages(), |m| m.encode(Encoding::Json)) {
+            let text = extend(frame);
+            let decoded = ClientMessage::decode(text.as_bytes());
+            assert!(decoded.is_err(), "{text:?} decoded as {decoded:?}");
+        }
+        for frame in frames(server_messages(), |m| m.encode(Encoding::Json)) {
+            let text = extend(frame);
+            let decoded = ServerMessage::decode(text.as_bytes());
+            assert!(decoded.is_err(), "{text:?} decoded as {decoded:?}");
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+use crate::binary::{Binary, Reader, Writer};
+use crate::{DecodeError, Map, Player};
+
+/// A movement intent sent by a client. The server decides where it ends up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    /// Where `player` ends up after one step in this direction, or `None`
+    /// if the map is in the way. Squares are two cells wide, so sideways
+    /// steps cover two columns.
+    ///
+    /// Only the map is checked here; the server also keeps players from
+    /// walking into each other.
+    pub fn step(self, player: &Player, map: &Map) -> Option<(u16, u16)> {
+        let (x, y) = (player.x, player.y);
+        let (x, y) = match self {
+            Direction::Up => (x, y.checked_sub(1)?),
+            Direction::Down => (x, y.checked_add(1)?),
+            Direction::Left => (x.checked_sub(2)?, y),
+            Direction::Right => (x.checked_add(2)?, y),
+        };
+        map.fits(x, y).then_some((x, y))
+    }
+}
+
+/// Whether a player standing at `(x, y)` would overlap `other`.
+pub fn overlaps(x: u16, y: u16, other: &Player) -> bool {
+    y == other.y && x.abs_diff(other.x) < 2
+}
+
+/// A numbered movement intent. The server reports the last sequence it has
+/// processed in every snapshot so clients can replay the ones still in flight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Input {
+    pub sequence: u32,
+    pub direction: Direction,
+}
+
+impl Binary for Input {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.sequence).write(&self.direction);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            sequence: r.read()?,
+            direction: r.read()?,
+        })
+    }
+}
+
+impl Binary for Direction {
+    fn write(&self, w: &mut Writer) {
+        w.byte(match self {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+        });
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match r.byte()? {
+            0 => Ok(Direction::Up),
+            1 => Ok(Direction::Down),
+            2 => Ok(Direction::Left),
+            3 => Ok(Direction::Right),
+            _ => Err(DecodeError::InvalidValue("direction")),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::binary::{Binary, Reader, Writer};
+use crate::{DecodeError, Player, PlayerId};
+
+/// The players a client can see, keyed by id.
+pub type PlayerSet = BTreeMap<PlayerId, Player>;
+
+/// A numbered view of the world sent to one client.
+///
+/// Snapshots are deltas: `players` holds everyone that was added or changed
+/// since the `baseline` snapshot the client last acknowledged, and `removed`
+/// everyone who has gone since. A snapshot without a baseline is a full one.
+/// Only players within the client's view radius are sent, so these are also
+/// how it learns that someone came into view or went out of it.
+///
+/// `input` is the sequence number of the last [`Input`](crate::Input) the
+/// server applied for this client, or 0 before the first one.
+///
+/// `tick` is the server tick the snapshot was taken on. Ticks come at a
+/// fixed rate, so it tells how far apart two snapshots were taken even when
+/// the network bunches them up.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub sequence: u32,
+    /// The room these players are in, matching [`Welcome::room`]. A client
+    /// that missed the `WELCOME` for a new room ignores snapshots of it
+    /// rather than drawing them on the wrong map.
+    ///
+    /// [`Welcome::room`]: crate::Welcome::room
+    pub room: u16,
+    pub tick: u32,
+    pub baseline: Option<u32>,
+    pub input: u32,
+    pub players: Vec<Player>,
+    pub removed: Vec<PlayerId>,
+}
+
+impl Snapshot {
+    /// Builds the snapshot that turns `baseline` into `current`.
+    pub fn delta(
+        sequence: u32,
+        room: u16,
+        tick: u32,
+        input: u32,
+        baseline: Option<(u32, &PlayerSet)>,
+        current: &PlayerSet,
+    ) -> Self {
+        let Some((baseline_sequence, previous)) = baseline else {
+            return Self {
+                sequence,
+                room,
+                tick,
+                baseline: None,
+                input,
+                players: current.values().cloned().collect(),
+                removed: Vec::new(),
+            };
+        };
+
+        Self {
+            sequence,
+            room,
+            tick,
+            baseline: Some(baseline_sequence),
+            input,
+            players: current
+                .values()
+                .filter(|player| previous.get(&player.id) != Some(*player))
+                .cloned()
+                .collect(),
+            removed: previous
+                .keys()
+                .filter(|id| !current.contains_key(id))
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Rebuilds the full player set from the baseline this snapshot was
+    /// computed against.
+    pub fn apply(&self, baseline: Option<&PlayerSet>) -> PlayerSet {
+        let mut players = baseline.cloned().unwrap_or_default();
+        for id in &self.removed {
+            players.remove(id);
+        }
+        for player in &self.players {
+            players.insert(player.id, player.clone());
+        }
+        players
+    }
+
+    /// True when the snapshot carries no changes against its baseline.
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Binary for Snapshot {
+    fn write(&self, w: &mut Writer) {
+        w.write(&self.sequence)
+            .write(&self.room)
+            .write(&self.tick)
+            .write(&self.baseline)
+            .write(&self.input)
+            .write(&self.players)
+            .write(&self.removed);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            sequence: r.read()?,
+            room: r.read()?,
+            tick: r.read()?,
+            baseline: r.read()?,
+            input: r.read()?,
+            players: r.read()?,
+            removed: r.read()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(id: u32, x: u16) -> Player {
+        Player {
+            id: PlayerId(id),
+            name: format!("player{id}"),
+            color: Default::default(),
+            glyph: None,
+            presence: Default::default(),
+            x,
+            y: 4,
+        }
+    }
+
+    fn set(players: impl IntoIterator<Item = Player>) -> PlayerSet {
+        players.into_iter().map(|p| (p.id, p)).collect()
+    }
+
+    #[test]
+    fn delta_without_baseline_is_full() {
+        let current = set([player(1, 0), player(2, 6)]);
+        let snapshot = Snapshot::delta(5, 1, 90, 3, None, &current);
+        assert_eq!(snapshot.baseline, None);
+        assert_eq!(snapshot.players, vec![player(1, 0), player(2, 6)]);
+        assert!(snapshot.removed.is_empty());
+        assert_eq!(snapshot.apply(None), current);
+    }
+
+    #[test]
+    fn delta_carries_only_changes() {
+        let previous = set([player(1, 0), player(2, 6), player(3, 8)]);
+        let current = set([player(1, 0), player(2, 7), player(4, 2)]);
+        let snapshot = Snapshot::delta(5, 1, 90, 3, Some((4, &previous)), &current);
+        assert_eq!(snapshot.baseline, Some(4));
+        assert_eq!(snapshot.players, vec![player(2, 7), player(4, 2)]);
+        assert_eq!(snapshot.removed, vec![PlayerId(3)]);
+        assert_eq!(snapshot.apply(Some(&previous)), current);
+    }
+
+    #[test]
+    fn unchanged_delta_is_empty() {
+        let players = set([player(1, 0), player(2, 6)]);
+        let snapshot = Snapshot::delta(5, 1, 90, 3, Some((4, &players)), &players);
+        assert!(snapshot.is_empty());
+        assert_eq!(snapshot.apply(Some(&players)), players);
+    }
+}
@@ -0,0 +1,148 @@
+//! How finding players near each other scales with the number of players,
+//! with the spatial index and with a plain scan over everyone.
+//!
+//! Run with `cargo bench -p server`.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use roam_protocol::Map;
+use server::spatial::SpatialIndex;
+
+/// The largest map a room may have: as wide as allowed, and as tall as the
+/// tile limit leaves room for at that width.
+const WIDTH: u16 = Map::MAX_WIDTH as u16;
+const HEIGHT: u16 = (Map::MAX_TILES / Map::MAX_WIDTH) as u16;
+
+/// The server's default view radius.
+const VIEW_RADIUS: u16 = 32;
+
+const PLAYER_COUNTS: [usize; 4] = [100, 1_000, 5_000, 10_000];
+
+/// `count` players spread over the map, the same every run.
+fn positions(count: usize) -> Vec<(u16, u16)> {
+    let mut state: u32 = 0x9e37_79b9;
+    let mut next = move |bound: u16| {
+        // xorshift, so the benchmark needs no random number crate.
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        (state % u32::from(bound)) as u16
+    };
+    (0..count)
+        .map(|_| (next(WIDTH / 2) * 2, next(HEIGHT)))
+        .collect()
+}
+
+fn index(positions: &[(u16, u16)]) -> SpatialIndex<usize> {
+    let mut index = SpatialIndex::new(WIDTH, HEIGHT);
+    for (key, &(x, y)) in positions.iter().enumerate() {
+        index.insert(key, x, y);
+    }
+    index
+}
+
+fn in_range(a: (u16, u16), b: (u16, u16), radius: u16) -> bool {
+    let dx = u32::from((a.0 / 2).abs_diff(b.0 / 2));
+    let dy = u32::from(a.1.abs_diff(b.1));
+    dx * dx + dy * dy <= u32::from(radius) * u32::from(radius)
+}
+
+/// What every broadcast does: find who each player can see.
+fn visible_sets(c: &mut Criterion) {
+    let mut group = c.benchmark_group("visible_sets");
+    for count in PLAYER_COUNTS {
+        let positions = positions(count);
+        let index = index(&positions);
+        group.bench_with_input(
+            BenchmarkId::new("index", count),
+            &positions,
+            |b, positions| {
+                b.iter(|| {
+                    positions
+                        .iter()
+                        .map(|&(x, y)| index.within(x, y, VIEW_RADIUS).count())
+                        .sum::<usize>()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("scan", count),
+            &positions,
+            |b, positions| {
+                b.iter(|| {
+                    positions
+                        .iter()
+                        .map(|&a| {
+                            positions
+                                .iter()
+                                .filter(|&&b| in_range(a, b, VIEW_RADIUS))
+                                .count()
+                        })
+                        .sum::<usize>()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// What every move does: check whether anyone is in the way.
+fn collisions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collisions");
+    for count in PLAYER_COUNTS {
+        let positions = positions(count);
+        let index = index(&positions);
+        group.bench_with_input(
+            BenchmarkId::new("index", count),
+            &positions,
+            |b, positions| {
+                b.iter(|| {
+                    positions
+                        .iter()
+                        .filter(|&&(x, y)| index.overlapping(x + 2, y).next().is_some())
+                        .count()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("scan", count),
+            &positions,
+            |b, positions| {
+                b.iter(|| {
+                    positions
+                        .iter()
+                        .filter(|&&(x, y)| {
+                            positions
+                                .iter()
+                                .any(|&(ox, oy)| oy == y && (x + 2).abs_diff(ox) < 2)
+                        })
+                        .count()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// What keeping the index up to date costs: everyone takes a step.
+fn moves(c: &mut Criterion) {
+    let mut group = c.benchmark_group("moves");
+    for count in PLAYER_COUNTS {
+        let positions = positions(count);
+        let mut index = index(&positions);
+        let mut step = 0;
+        group.bench_function(BenchmarkId::new("index", count), |b| {
+            b.iter(|| {
+                step = (step + 2) % 4;
+                for (key, &(x, y)) in positions.iter().enumerate() {
+                    index.insert(key, black_box(x.saturating_add(step)), y);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, visible_sets, collisions, moves);
+criterion_main!(benches);
@@ -0,0 +1,245 @@
+use std::{fs, path::PathBuf};
+
+use clap::{Arg, ArgMatches, Command, builder::BoolishValueParser, value_parser};
+use log::LevelFilter;
+use serde::Deserialize;
+
+/// How long a connection survives without hearing from its client, in
+/// seconds, when nothing else is configured.
+const DEFAULT_PLAYER_LIFETIME: u32 = 60;
+
+/// How long a timed-out player can still be resumed with its token, in
+/// seconds, when nothing else is configured.
+const DEFAULT_RESUME_WINDOW: u32 = 300;
+
+/// How far nearby chat carries when nothing else is configured.
+const DEFAULT_SAY_RADIUS: u16 = 12;
+
+/// How far players can see each other when nothing else is configured.
+/// Enough to cover a large terminal with our square in the middle.
+const DEFAULT_VIEW_RADIUS: u16 = 32;
+
+/// Ticks per second when nothing else is configured.
+const DEFAULT_TICK_RATE: u32 = 30;
+
+/// Most ticks per second, which keeps a tick at least a millisecond long.
+const MAX_TICK_RATE: u32 = 1000;
+
+/// Everything about the server that can be changed without rebuilding it.
+///
+/// Each setting is taken from the first of its command-line flag, its
+/// environment variable, the config file named by `--config` and its
+/// default. The config file uses the same names as the flags, with
+/// underscores for dashes.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address to listen on for datagrams.
+    pub bind: String,
+    /// Rooms to host, as described in [`load_world`](crate::load_world).
+    /// The built-in world when unset.
+    pub world_file: Option<PathBuf>,
+    /// Ticks per second.
+    pub tick_rate: u32,
+    /// Snapshots sent to each client per second, at most one per tick. The
+    /// tick rate when unset.
+    pub broadcast_rate: Option<u32>,
+    /// Seconds a connection survives without hearing from its client.
+    pub player_lifetime: u32,
+    /// Seconds a timed-out player can still be resumed with its token.
+    pub resume_window: u32,
+    /// Most players connected at once. Unlimited when unset.
+    pub max_players: Option<usize>,
+    /// Whether players block each other outside social zones.
+    pub player_collisions: bool,
+    /// How far, in squares, players can hear nearby chat.
+    pub say_radius: u16,
+    /// How far, in squares, players are sent each other's whereabouts.
+    pub view_radius: u16,
+    pub log_level: LevelFilter,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:3000".to_string(),
+            world_file: None,
+            tick_rate: DEFAULT_TICK_RATE,
+            broadcast_rate: None,
+            player_lifetime: DEFAULT_PLAYER_LIFETIME,
+            resume_window: DEFAULT_RESUME_WINDOW,
+            max_players: None,
+            player_collisions: true,
+            say_radius: DEFAULT_SAY_RADIUS,
+            view_radius: DEFAULT_VIEW_RADIUS,
+            log_level: LevelFilter::Info,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration from the command line, the environment and
+    /// the config file. Bad flags end the process with clap's usage message;
+    /// a bad config file is reported as an error.
+    pub fn load() -> Result<Self, String> {
+        let matches = command().get_matches();
+        let mut config = match matches.get_one::<PathBuf>("config") {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                toml::from_str(&text).map_err(|e| format!("invalid {}: {e}", path.display()))?
+            }
+            None => Self::default(),
+        };
+        override_with(&matches, "bind", &mut config.bind);
+        override_some(&matches, "world-file", &mut config.world_file);
+        override_with(&matches, "tick-rate", &mut config.tick_rate);
+        override_some(&matches, "broadcast-rate", &mut config.broadcast_rate);
+        override_with(&matches, "player-lifetime", &mut config.player_lifetime);
+        override_with(&matches, "resume-window", &mut config.resume_window);
+        override_some(&matches, "max-players", &mut config.max_players);
+        override_with(&matches, "player-collisions", &mut config.player_collisions);
+        override_with(&matches, "say-radius", &mut config.say_radius);
+        override_with(&matches, "view-radius", &mut config.view_radius);
+        override_with(&matches, "log-level", &mut config.log_level);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Ticks between snapshots, so clients get about `broadcast_rate` a
+    /// second.
+    pub fn ticks_per_broadcast(&self) -> u32 {
+        self.broadcast_rate
+            .map_or(1, |rate| (self.tick_rate / rate).max(1))
+    }
+
+    /// Catches settings the file could set to something the flags would
+    /// have refused.
+    fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_TICK_RATE).contains(&self.tick_rate) {
+            return Err(format!("tick_rate must be between 1 and {MAX_TICK_RATE}"));
+        }
+        if self.broadcast_rate == Some(0) {
+            return Err("broadcast_rate must be at least 1".to_string());
+        }
+        if self.player_lifetime == 0 {
+            return Err("player_lifetime must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn command() -> Command {
+    Command::new("roam-server")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Hosts a roam world for clients to connect to")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .env("CONFIG_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("TOML file to read settings from, overridden by flags and the environment"),
+        )
+        .arg(
+            Arg::new("bind")
+                .short('b')
+                .long("bind")
+                .env("BIND_ADDR")
+                .help("Address to listen on [default: 0.0.0.0:3000]"),
+        )
+        .arg(
+            Arg::new("world-file")
+                .short('w')
+                .long("world-file")
+                .env("WORLD_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Rooms to host, as TOML, or a single map [default: built-in world]"),
+        )
+        .arg(
+            Arg::new("tick-rate")
+                .long("tick-rate")
+                .env("TICK_RATE")
+                .value_parser(value_parser!(u32).range(1..=i64::from(MAX_TICK_RATE)))
+                .help("Ticks per second [default: 30]"),
+        )
+        .arg(
+            Arg::new("broadcast-rate")
+                .long("broadcast-rate")
+                .env("BROADCAST_RATE")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Snapshots sent to each client per second [default: the tick rate]"),
+        )
+        .arg(
+            Arg::new("player-lifetime")
+                .long("player-lifetime")
+                .env("PLAYER_LIFETIME")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Seconds before a silent client times out [default: 60]"),
+        )
+        .arg(
+            Arg::new("resume-window")
+                .long("resume-window")
+                .env("RESUME_WINDOW")
+                .value_parser(value_parser!(u32))
+                .help("Seconds a timed-out player can be resumed [default: 300]"),
+        )
+        .arg(
+            Arg::new("max-players")
+                .long("max-players")
+                .env("MAX_PLAYERS")
+                .value_parser(value_parser!(usize))
+                .help("Most players connected at once [default: unlimited]"),
+        )
+        .arg(
+            Arg::new("player-collisions")
+                .long("player-collisions")
+                .env("PLAYER_COLLISIONS")
+                .value_parser(BoolishValueParser::new())
+                .help("Whether players block each other outside social zones [default: on]"),
+        )
+        .arg(
+            Arg::new("say-radius")
+                .long("say-radius")
+                .env("SAY_RADIUS")
+                .value_parser(value_parser!(u16))
+                .help("How far nearby chat carries, in squares [default: 12]"),
+        )
+        .arg(
+            Arg::new("view-radius")
+                .long("view-radius")
+                .env("VIEW_RADIUS")
+                .value_parser(value_parser!(u16))
+                .help("How far players see each other, in squares [default: 32]"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .short('l')
+                .long("log-level")
+                .env("LOG_LEVEL")
+                .value_parser(value_parser!(LevelFilter))
+                .help("One of off, error, warn, info, debug or trace [default: info]"),
+        )
+}
+
+/// Replaces `setting` with the value given for `id`, if any.
+fn override_with<T: Clone + Send + Sync + 'static>(
+    matches: &ArgMatches,
+    id: &str,
+    setting: &mut T,
+) {
+    if let Some(value) = matches.get_one::<T>(id) {
+        *setting = value.clone();
+    }
+}
+
+/// Like [`override_with`], for settings that may be left unset.
+fn override_some<T: Clone + Send + Sync + 'static>(
+    matches: &ArgMatches,
+    id: &str,
+    setting: &mut Option<T>,
+) {
+    if let Some(value) = matches.get_one::<T>(id) {
+        *setting = Some(value.clone());
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use roam_protocol::{Encoding, Player, PlayerId, PlayerSet, Snapshot};
+
+/// Moves a client may make per second. Anything above that is dropped, so a
+/// modified client cannot outrun everyone else.
+const MOVES_PER_SECOND: u32 = 30;
+
+/// Chat messages and emotes a client may send per second, to keep anyone
+/// from flooding everyone else's screen.
+const CHATS_PER_SECOND: u32 = 3;
+
+/// Datagrams we could not read that a client is told about per second.
+/// Anything above that goes unanswered, so a broken client cannot keep us
+/// busy replying.
+const ERRORS_PER_SECOND: u32 = 3;
+
+/// How many times a `WELCOME` is sent again while the client has not
+/// acknowledged a snapshot, before we give up on it. An address that has not
+/// confirmed gets as many seconds to do so, without resends.
+const WELCOME_RESENDS: u32 = 3;
+
+/// How many unacknowledged snapshots are kept around as possible baselines.
+const SNAPSHOT_HISTORY: usize = 32;
+
+/// Per-address state for a connected client.
+#[derive(Debug)]
+pub struct Connection {
+    /// Seconds left before the connection times out.
+    pub lifetime: u32,
+    /// Seconds the connection survives without hearing from its client.
+    timeout: u32,
+    pub encoding: Encoding,
+    pub resume_token: u64,
+    /// Index of the room the player is in. Changed with [`Connection::enter`].
+    pub room: usize,
+    /// Sequence number of the last input applied, reported back in snapshots.
+    pub last_input: u32,
+    /// Moves left in the current second.
+    moves_left: u32,
+    /// Chat messages and emotes left in the current second.
+    chats_left: u32,
+    /// Error replies left in the current second.
+    errors_left: u32,
+    /// `WELCOME`s left to send again for the current room.
+    welcomes_left: u32,
+    /// Whether the client has ever echoed its resume token, acknowledged a
+    /// snapshot or resumed a session. Until then, the address may not even be
+    /// the client's, so it is sent nothing but `WELCOME`s.
+    confirmed: bool,
+    next_sequence: u32,
+    acked: Option<u32>,
+    /// Whether the last two snapshots we sent were identical. Until they
+    /// are, the client cannot tell players stopping from lost datagrams.
+    settled: bool,
+    /// Snapshots we have sent, oldest first, starting at the acked one.
+    sent: VecDeque<Sent>,
+    /// Everyone within the client's view radius as of the last broadcast.
+    in_view: BTreeSet<PlayerId>,
+}
+
+#[derive(Debug)]
+struct Sent {
+    sequence: u32,
+    input: u32,
+    players: PlayerSet,
+}
+
+impl Connection {
+    pub fn new(encoding: Encoding, resume_token: u64, room: usize, timeout: u32) -> Self {
+        Self {
+            lifetime: timeout,
+            timeout,
+            encoding,
+            resume_token,
+            room,
+            last_input: 0,
+            moves_left: MOVES_PER_SECOND,
+            chats_left: CHATS_PER_SECOND,
+            errors_left: ERRORS_PER_SECOND,
+            welcomes_left: WELCOME_RESENDS,
+            confirmed: false,
+            next_sequence: 1,
+            acked: None,
+            settled: false,
+            sent: VecDeque::new(),
+            in_view: BTreeSet::new(),
+        }
+    }
+
+    /// Moves the connection to another room, whose `WELCOME` gets a fresh
+    /// round of resends.
+    pub fn enter(&mut self, room: usize) {
+        self.room = room;
+        self.welcomes_left = WELCOME_RESENDS;
+    }
+
+    /// Spends one resend of the `WELCOME`, returning false once they have
+    /// run out.
+    pub fn take_welcome_resend(&mut self) -> bool {
+        if self.welcomes_left == 0 {
+            return false;
+        }
+        self.welcomes_left -= 1;
+        true
+    }
+
+    /// Called whenever a valid packet arrives from this client.
+    pub fn refresh(&mut self) {
+        self.lifetime = self.timeout;
+    }
+
+    /// Spends one move from this second's budget, returning false when the
+    /// client is moving faster than allowed.
+    pub fn take_move(&mut self) -> bool {
+        if self.moves_left == 0 {
+            return false;
+        }
+        self.moves_left -= 1;
+        true
+    }
+
+    /// Spends one chat message or emote from this second's budget,
+    /// returning false when the client is chatting faster than allowed.
+    pub fn take_chat(&mut self) -> bool {
+        if self.chats_left == 0 {
+            return false;
+        }
+        self.chats_left -= 1;
+        true
+    }
+
+    /// Spends one error reply from this second's budget, returning false
+    /// when the client has been told about enough bad datagrams for now.
+    pub fn take_error(&mut self) -> bool {
+        if self.errors_left == 0 {
+            return false;
+        }
+        self.errors_left -= 1;
+        true
+    }
+
+    /// Called once a second to hand out fresh move, chat and error budgets.
+    pub fn refill_budgets(&mut self) {
+        self.moves_left = MOVES_PER_SECOND;
+        self.chats_left = CHATS_PER_SECOND;
+        self.errors_left = ERRORS_PER_SECOND;
+    }
+
+    /// Builds the next snapshot for this client as a delta against the last
+    /// one it acknowledged. Returns `None` when nothing changed since then,
+    /// including which of its inputs we have applied, and the client has
+    /// already been sent one unchanged snapshot after the last change.
+    pub fn snapshot(&mut self, tick: u32, players: PlayerSet) -> Option<Snapshot> {
+        let baseline = self
+            .acked
+            .and_then(|acked| self.sent.iter().find(|sent| sent.sequence == acked));
+        let snapshot = Snapshot::delta(
+            self.next_sequence,
+            self.room as u16,
+            tick,
+            self.last_input,
+            baseline.map(|sent| (sent.sequence, &sent.players)),
+            &players,
+        );
+        let unchanged =
+            baseline.is_some_and(|sent| sent.input == self.last_input) && snapshot.is_empty();
+        if unchanged && self.settled {
+            return None;
+        }
+        self.settled = self
+            .sent
+            .back()
+            .is_some_and(|last| last.input == self.last_input && last.players == players);
+
+        self.next_sequence += 1;
+        self.sent.push_back(Sent {
+            sequence: snapshot.sequence,
+            input: self.last_input,
+            players,
+        });
+        if self.sent.len() > SNAPSHOT_HISTORY {
+            self.sent.pop_front();
+        }
+        Some(snapshot)
+    }
+
+    /// Forgets the snapshots sent so far, so the next one is a full one.
+    /// Clients drop their own history on every `WELCOME`, so this goes with
+    /// sending one. Sequence numbers carry on, which keeps acks still in
+    /// flight from matching anything.
+    pub fn restart(&mut self) {
+        self.acked = None;
+        self.settled = false;
+        self.sent.clear();
+        self.in_view.clear();
+    }
+
+    /// Records who the client can see now.
+    pub fn set_view(&mut self, visible: &PlayerSet) {
+        self.in_view = visible.keys().copied().collect();
+    }
+
+    pub fn can_see(&self, id: PlayerId) -> bool {
+        self.in_view.contains(&id)
+    }
+
+    /// Drops a player who left the game from the view, returning whether the
+    /// client could see it.
+    pub fn forget(&mut self, id: PlayerId) -> bool {
+        self.in_view.remove(&id)
+    }
+
+    /// Whether the client has acknowledged a snapshot since its last
+    /// `WELCOME`, which shows the `WELCOME` arrived.
+    pub fn is_welcomed(&self) -> bool {
+        self.acked.is_some()
+    }
+
+    /// Whether the client has shown it really is at this address, by echoing
+    /// its resume token or acknowledging a snapshot, or is known some other
+    /// way.
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed
+    }
+
+    pub fn confirm(&mut self) {
+        self.confirmed = true;
+    }
+
+    pub fn acknowledge(&mut self, sequence: u32) {
+        let known = self.sent.iter().any(|sent| sent.sequence == sequence);
+        if known && self.acked.is_none_or(|acked| sequence > acked) {
+            self.acked = Some(sequence);
+            self.confirm();
+            // Older snapshots can never be a baseline again.
+            self.sent.retain(|sent| sent.sequence >= sequence);
+        }
+    }
+}
+
+/// A player whose connection timed out, waiting to be resumed in the room
+/// it left for `lifetime` more seconds.
+#[derive(Debug)]
+pub struct Departed {
+    pub player: Player,
+    pub room: usize,
+    pub lifetime: u32,
+}
+
+impl Departed {
+    pub fn new(player: Player, room: usize, lifetime: u32) -> Self {
+        Self {
+            player,
+            room,
+            lifetime,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players(xs: &[u16]) -> PlayerSet {
+        xs.iter()
+            .enumerate()
+            .map(|(id, &x)| {
+                let id = PlayerId(id as u32);
+                let player = Player {
+                    id,
+                    name: format!("player{}", id.0),
+                    color: Default::default(),
+                    glyph: None,
+                    presence: Default::default(),
+                    x,
+                    y: 2,
+                };
+                (id, player)
+            })
+            .collect()
+    }
+
+    fn connection() -> Connection {
+        Connection::new(Encoding::Binary, 1, 0, 10)
+    }
+
+    #[test]
+    fn acked_snapshot_becomes_the_baseline() {
+        let mut connection = connection();
+        let first = connection.snapshot(1, players(&[0, 4])).unwrap();
+        assert_eq!(first.baseline, None);
+        assert!(!connection.is_welcomed());
+
+        connection.acknowledge(first.sequence);
+        assert!(connection.is_welcomed());
+        assert!(connection.is_confirmed());
+        let second = connection.snapshot(2, players(&[0, 6])).unwrap();
+        assert_eq!(second.baseline, Some(first.sequence));
+        // Only the player who moved.
+        assert_eq!(second.players.len(), 1);
+        assert_eq!(
+            (second.players[0].id, second.players[0].x),
+            (PlayerId(1), 6)
+        );
+    }
+
+    #[test]
+    fn unacked_snapshots_stay_full() {
+        let mut connection = connection();
+        for tick in 1..4 {
+            let snapshot = connection.snapshot(tick, players(&[0, 4])).unwrap();
+            assert_eq!(snapshot.baseline, None);
+            assert_eq!(snapshot.players.len(), 2);
+        }
+    }
+
+    #[test]
+    fn unchanged_snapshots_settle_after_one_repeat() {
+        let mut connection = connection();
+        let first = connection.snapshot(1, players(&[0, 4])).unwrap();
+        connection.acknowledge(first.sequence);
+        // One empty delta, so the client sees the players have stopped.
+        assert!(connection.snapshot(2, players(&[0, 4])).unwrap().is_empty());
+        assert_eq!(connection.snapshot(3, players(&[0, 4])), None);
+        assert_eq!(connection.snapshot(4, players(&[0, 4])), None);
+
+        // A newly applied input is news even if nobody moved, until the
+        // client acknowledges hearing of it.
+        connection.last_input = 7;
+        let snapshot = connection.snapshot(5, players(&[0, 4])).unwrap();
+        assert_eq!(snapshot.input, 7);
+        assert!(connection.snapshot(6, players(&[0, 4])).is_some());
+        connection.acknowledge(snapshot.sequence);
+        assert_eq!(connection.snapshot(7, players(&[0, 4])), None);
+
+        assert!(connection.snapshot(8, players(&[1, 4])).is_some());
+    }
+
+    #[test]
+    fn unknown_and_stale_acks_are_ignored() {
+        let mut connection = connection();
+        assert!(connection.snapshot(1, players(&[0])).is_some());
+        connection.acknowledge(99);
+        assert!(!connection.is_welcomed());
+        assert!(!connection.is_confirmed());
+
+        let second = connection.snapshot(2, players(&[1])).unwrap();
+        connection.acknowledge(second.sequence);
+        connection.acknowledge(second.sequence - 1);
+        let third = connection.snapshot(3, players(&[2])).unwrap();
+        assert_eq!(third.baseline, Some(second.sequence));
+    }
+
+    #[test]
+    fn restart_sends_a_full_snapshot() {
+        let mut connection = connection();
+        let first = connection.snapshot(1, players(&[0, 4])).unwrap();
+        connection.acknowledge(first.sequence);
+        connection.restart();
+        assert!(!connection.is_welcomed());
+        let next = connection.snapshot(2, players(&[0, 4])).unwrap();
+        assert_eq!(next.baseline, None);
+        assert!(next.sequence > first.sequence);
+        assert_eq!(next.players.len(), 2);
+    }
+}
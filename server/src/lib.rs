@@ -0,0 +1,4 @@
+//! Parts of the roam server that stand on their own, kept in a library so
+//! the benchmarks can reach them.
+
+pub mod spatial;
@@ -0,0 +1,25 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Writes log records to standard output, one line each, where `docker
+/// compose logs` picks them up.
+struct StdoutLogger;
+
+impl Log for StdoutLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            println!("{:<5} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sends log records at `level` and above to standard output.
+pub fn init(level: LevelFilter) {
+    log::set_logger(&StdoutLogger).expect("logger is only set once");
+    log::set_max_level(level);
+}
@@ -1,134 +1,193 @@
+mod config;
+mod connection;
+mod logger;
+mod room;
+mod world;
+
 use std::{
-    collections::HashMap,
+    fs,
+    io::ErrorKind,
     net::{SocketAddr, UdpSocket},
-    sync::{Arc, Mutex, mpsc},
-    thread,
-    time::Duration,
+    path::Path,
+    process,
+    time::{Duration, Instant},
 };
 
-use serde::{Deserialize, Serialize};
+use log::{debug, info, warn};
+use roam_protocol::{ClientMessage, MAX_DATAGRAM_SIZE, Map};
 
-enum Event {
-    Tick(u32),
-    NewConnection(SocketAddr),
-    UpdatePlayer(SocketAddr, Player),
-    BroadcastPlayers,
-}
+use crate::config::Config;
+use crate::room::{Room, parse_world};
+use crate::world::{Settings, World};
 
-fn main() {
-    let addr = "0.0.0.0:3000";
-    let socket = UdpSocket::bind(addr).unwrap();
-    println!("Binding to {}", addr);
+/// Most ticks run back to back after the server stalls.
+const MAX_CATCH_UP_TICKS: u32 = 5;
 
-    let (event_tx, event_rx) = mpsc::channel::<Event>();
+/// How late a tick may start while datagrams keep arriving, which saves
+/// setting the socket's read timeout before every one of them.
+const TICK_SLACK: Duration = Duration::from_millis(1);
 
-    let server_tick = event_tx.clone();
-    thread::spawn(move || {
-        loop {
-            server_tick.send(Event::Tick(1)).unwrap();
-            thread::sleep(Duration::from_millis(1000));
-        }
-    });
-    let client_tick = event_tx.clone();
-    thread::spawn(move || {
-        loop {
-            client_tick.send(Event::BroadcastPlayers).unwrap();
-            thread::sleep(Duration::from_millis(33));
-        }
+/// The world used when no world file is configured.
+const DEFAULT_WORLD: &str = include_str!("../world.toml");
+
+fn main() {
+    // Reported like clap reports bad flags.
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        process::exit(2);
     });
+    logger::init(config.log_level);
+    debug!("{config:?}");
+
+    let socket = UdpSocket::bind(&config.bind)
+        .unwrap_or_else(|e| panic!("Failed to bind to {}: {e}", config.bind));
+    info!("Binding to {}", config.bind);
+
+    let rooms = load_world(config.world_file.as_deref());
+    for room in &rooms {
+        info!(
+            "Loaded room {:?}, a {}x{} map",
+            room.name,
+            room.map.width(),
+            room.map.height()
+        );
+    }
 
+    let settings = Settings {
+        player_collisions: config.player_collisions,
+        say_radius: config.say_radius,
+        view_radius: config.view_radius,
+        tick_rate: config.tick_rate,
+        player_lifetime: config.player_lifetime,
+        resume_window: config.resume_window,
+        max_players: config.max_players,
+    };
     let mut server = Server {
         socket,
-        players: Arc::new(Mutex::new(HashMap::new())),
-        connections: Arc::new(Mutex::new(HashMap::new())),
+        tick_duration: Duration::from_secs(1) / config.tick_rate,
+        ticks_per_broadcast: config.ticks_per_broadcast(),
+        ticks_since_broadcast: 0,
+        world: World::new(rooms, settings),
     };
 
-    server.run(event_tx, event_rx);
+    server.run();
 }
 
+/// Runs the world on the thread that owns the socket, so nothing is shared
+/// and nothing needs locking.
 struct Server {
     socket: UdpSocket,
-    players: Arc<Mutex<HashMap<SocketAddr, Player>>>,
-    connections: Arc<Mutex<HashMap<SocketAddr, u32>>>,
+    tick_duration: Duration,
+    /// Ticks between snapshots to each client.
+    ticks_per_broadcast: u32,
+    ticks_since_broadcast: u32,
+    world: World,
 }
 
 impl Server {
-    fn run(&mut self, event_tx: mpsc::Sender<Event>, event_rx: mpsc::Receiver<Event>) {
-        const PLAYER_LIFETIME: u32 = 60;
-        let event_tx_clone = event_tx.clone();
-        let socket_clone = self.socket.try_clone().unwrap();
-        thread::spawn(move || {
-            loop {
-                let mut buf = [0; 1024];
-                match socket_clone.recv_from(&mut buf) {
-                    Ok((size, addr)) => {
-                        if let Ok(msg) = std::str::from_utf8(&buf[..size]) {
-                            let trimmed = msg.trim();
-                            if trimmed == "CONNECT" {
-                                event_tx_clone.send(Event::NewConnection(addr)).unwrap();
-                            } else if let Ok(player) = serde_json::from_str::<Player>(trimmed) {
-                                event_tx_clone
-                                    .send(Event::UpdatePlayer(addr, player))
-                                    .unwrap();
-                            }
-                        }
-                    }
-                    Err(e) => println!("recv function failed: {e:?}"),
-                }
-            }
-        });
-
+    fn run(&mut self) {
+        // Datagrams are handled as they come in; the world moves on in fixed
+        // ticks in between.
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let mut next_tick = Instant::now() + self.tick_duration;
+        // The read timeout counts from the start of each read, so one set
+        // early in a tick lets later reads wait past the tick. Setting it is a
+        // syscall, so it is set once per tick and again only once a read could
+        // overshoot by more than the slack.
+        let mut read_timeout: Option<(Instant, Duration)> = None;
         loop {
-            match event_rx.recv().unwrap() {
-                Event::Tick(tick_amt) => {
-                    let mut connections = self.connections.lock().unwrap();
-                    for (_, value) in connections.iter_mut() {
-                        if *value > 0 {
-                            *value -= tick_amt;
-                        }
-                    }
-                    connections.retain(|_, v| *v > 0);
-                    let active_addrs: std::collections::HashSet<SocketAddr> =
-                        connections.keys().cloned().collect();
-                    let mut players = self.players.lock().unwrap();
-                    players.retain(|addr, _| active_addrs.contains(addr));
-                    println!(
-                        "Active connections: {:?}, Players: {:?}",
-                        *connections, *players
-                    );
-                }
-                Event::NewConnection(addr) => {
-                    let player = Player { x: 0, y: 0 };
-                    let mut players = self.players.lock().unwrap();
-                    players.insert(addr, player);
-                    let mut connections = self.connections.lock().unwrap();
-                    connections.insert(addr, PLAYER_LIFETIME);
+            let now = Instant::now();
+            if now < next_tick {
+                let remaining = next_tick - now;
+                if read_timeout.is_none_or(|(deadline, timeout)| {
+                    deadline != next_tick || timeout > remaining + TICK_SLACK
+                }) {
+                    self.socket
+                        .set_read_timeout(Some(remaining))
+                        .expect("timeout is not zero");
+                    read_timeout = Some((next_tick, remaining));
                 }
-                Event::UpdatePlayer(addr, player) => {
-                    let mut players = self.players.lock().unwrap();
-                    players.insert(addr, player);
-                }
-                Event::BroadcastPlayers => {
-                    let players = self.players.lock().unwrap();
-                    let connections = self.connections.lock().unwrap();
-                    for (addr, _) in connections.iter() {
-                        let others: Vec<Player> = players
-                            .iter()
-                            .filter(|(a, _)| *a != addr)
-                            .map(|(_, p)| p.clone())
-                            .collect();
-                        let json = serde_json::to_string(&others).unwrap();
-                        let message = format!("PLAYERS{}\n", json);
-                        let _ = self.socket.send_to(message.as_bytes(), *addr);
-                    }
+                match self.socket.recv_from(&mut buf) {
+                    Ok((size, addr)) => self.receive(addr, &buf[..size]),
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    Err(e) => warn!("recv function failed: {e:?}"),
                 }
+                self.flush();
+                continue;
+            }
+
+            // After a stall, run the ticks we missed so timers keep up, but
+            // only a few: past that, skip ahead rather than fall further
+            // behind trying to catch up. Clients only need the latest
+            // snapshot, so there is at most one broadcast however many ticks
+            // ran.
+            let behind = now.duration_since(next_tick).as_nanos() / self.tick_duration.as_nanos();
+            let due = u32::try_from(behind + 1).unwrap_or(u32::MAX);
+            for _ in 0..due.min(MAX_CATCH_UP_TICKS) {
+                self.world.step();
+                self.ticks_since_broadcast += 1;
+            }
+            if self.ticks_since_broadcast >= self.ticks_per_broadcast {
+                self.ticks_since_broadcast = 0;
+                self.world.broadcast();
             }
+            self.flush();
+            if due > MAX_CATCH_UP_TICKS {
+                warn!(
+                    "Fell {due} ticks behind, skipping {}",
+                    due - MAX_CATCH_UP_TICKS
+                );
+                next_tick = now + self.tick_duration;
+            } else {
+                next_tick += self.tick_duration * due;
+            }
+        }
+    }
+
+    fn receive(&mut self, addr: SocketAddr, datagram: &[u8]) {
+        match ClientMessage::decode(datagram) {
+            Ok(message) => self.world.handle(addr, message),
+            Err(e) => self.world.reject(addr, e),
+        }
+    }
+
+    /// Sends whatever the world has queued up.
+    fn flush(&mut self) {
+        for (addr, datagram) in self.world.take_outgoing() {
+            let _ = self.socket.send_to(&datagram, addr);
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct Player {
-    pub x: u16,
-    pub y: u16,
+/// Reads the rooms from the world file at `path`, falling back to the
+/// built-in world. A `.toml` file lists rooms and their doors; anything else
+/// is a lone map, which becomes a room named after the file.
+fn load_world(path: Option<&Path>) -> Vec<Room> {
+    let Some(path) = path else {
+        return parse_world(DEFAULT_WORLD).expect("built-in world is valid");
+    };
+    let text = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read world file {}: {e}", path.display()));
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "toml")
+    {
+        return parse_world(&text)
+            .unwrap_or_else(|e| panic!("Invalid world file {}: {e}", path.display()));
+    }
+    let map: Map = text
+        .parse()
+        .unwrap_or_else(|e| panic!("Invalid world file {}: {e}", path.display()));
+    let name = path
+        .file_stem()
+        .map_or("world".into(), |stem| stem.to_string_lossy());
+    let room = Room::new(name.into_owned(), map);
+    if !room.has_spawn_point() {
+        panic!(
+            "Invalid world file {}: room {:?} has nowhere to stand",
+            path.display(),
+            room.name
+        );
+    }
+    vec![room]
 }
@@ -0,0 +1,292 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+};
+
+use roam_protocol::{Map, Player, Presence, Tile};
+use serde::Deserialize;
+use server::spatial::SpatialIndex;
+
+/// One map and everyone on it. Players only see, hear and bump into others
+/// in the same room.
+pub struct Room {
+    pub name: String,
+    pub map: Map,
+    /// The room each linked door tile leads to, by index.
+    doors: HashMap<(u16, u16), usize>,
+    players: HashMap<SocketAddr, Player>,
+    /// Where each player stands, kept in step with `players` so nobody has
+    /// to go through everyone to find who is near a spot.
+    index: SpatialIndex<SocketAddr>,
+}
+
+impl Room {
+    pub fn new(name: String, map: Map) -> Self {
+        let index = SpatialIndex::new(map.width(), map.height());
+        Self {
+            name,
+            map,
+            doors: HashMap::new(),
+            players: HashMap::new(),
+            index,
+        }
+    }
+
+    pub fn player(&self, addr: SocketAddr) -> Option<&Player> {
+        self.players.get(&addr)
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &Player> {
+        self.players.values()
+    }
+
+    pub fn insert(&mut self, addr: SocketAddr, player: Player) {
+        self.index.insert(addr, player.x, player.y);
+        self.players.insert(addr, player);
+    }
+
+    pub fn remove(&mut self, addr: SocketAddr) -> Option<Player> {
+        self.index.remove(addr);
+        self.players.remove(&addr)
+    }
+
+    pub fn move_to(&mut self, addr: SocketAddr, x: u16, y: u16) {
+        if let Some(player) = self.players.get_mut(&addr) {
+            (player.x, player.y) = (x, y);
+            self.index.insert(addr, x, y);
+        }
+    }
+
+    pub fn set_presence(&mut self, addr: SocketAddr, presence: Presence) {
+        if let Some(player) = self.players.get_mut(&addr) {
+            player.presence = presence;
+        }
+    }
+
+    /// Everyone within `radius` squares of `(x, y)`, by address.
+    pub fn near(&self, x: u16, y: u16, radius: u16) -> impl Iterator<Item = (SocketAddr, &Player)> {
+        self.index
+            .within(x, y, radius)
+            .filter_map(|addr| Some((addr, self.players.get(&addr)?)))
+    }
+
+    /// Everyone whose square would overlap one standing at `(x, y)`, by
+    /// address.
+    pub fn overlapping(&self, x: u16, y: u16) -> impl Iterator<Item = SocketAddr> {
+        self.index.overlapping(x, y)
+    }
+
+    /// The room a player at `(x, y)` is sent to, if its square covers a
+    /// linked door.
+    pub fn door(&self, x: u16, y: u16) -> Option<usize> {
+        [(x, y), (x.saturating_add(1), y)]
+            .iter()
+            .find_map(|at| self.doors.get(at))
+            .copied()
+    }
+
+    /// Whether players have somewhere to stand here other than on linked
+    /// doors.
+    pub fn has_spawn_point(&self) -> bool {
+        self.map
+            .spawn_points()
+            .any(|(x, y)| self.door(x, y).is_none())
+    }
+
+    /// The first free spot in the room, or the first spot at all if it is
+    /// full. Linked doors are skipped so nobody arrives only to be sent
+    /// straight on.
+    pub fn spawn_point(&self) -> (u16, u16) {
+        let spots = || {
+            self.map
+                .spawn_points()
+                .filter(|&(x, y)| self.door(x, y).is_none())
+        };
+        let free = |&(x, y): &(u16, u16)| self.overlapping(x, y).next().is_none();
+        spots()
+            .find(free)
+            .or_else(|| spots().next())
+            .unwrap_or((0, 0))
+    }
+}
+
+/// A world file: the rooms of the world, the first of which new players
+/// start in.
+///
+/// ```toml
+/// [[rooms]]
+/// name = "lobby"
+/// map = '''
+/// ######
+/// #....+
+/// ######
+/// '''
+/// doors = [{ at = [5, 1], to = "garden" }]
+/// ```
+#[derive(Deserialize)]
+struct WorldFile {
+    rooms: Vec<RoomFile>,
+}
+
+#[derive(Deserialize)]
+struct RoomFile {
+    name: String,
+    map: String,
+    #[serde(default)]
+    doors: Vec<DoorFile>,
+}
+
+/// A door tile and the name of the room it leads to. Doors left out of the
+/// file are ordinary floor.
+#[derive(Deserialize)]
+struct DoorFile {
+    at: (u16, u16),
+    to: String,
+}
+
+/// Parses a world file into its rooms, checking that every door is on a
+/// door tile and leads somewhere that exists.
+pub fn parse_world(text: &str) -> Result<Vec<Room>, String> {
+    let world: WorldFile = toml::from_str(text).map_err(|e| e.to_string())?;
+    if world.rooms.is_empty() {
+        return Err("the world has no rooms".to_string());
+    }
+    // Rooms are numbered on the wire with a u16.
+    if world.rooms.len() > usize::from(u16::MAX) + 1 {
+        return Err("the world has too many rooms".to_string());
+    }
+
+    let mut names = HashSet::new();
+    let mut rooms = Vec::with_capacity(world.rooms.len());
+    for room in &world.rooms {
+        if !names.insert(room.name.as_str()) {
+            return Err(format!("room {:?} is defined twice", room.name));
+        }
+        let map = room
+            .map
+            .parse()
+            .map_err(|e| format!("room {:?}: {e}", room.name))?;
+        rooms.push(Room::new(room.name.clone(), map));
+    }
+
+    for (index, room) in world.rooms.iter().enumerate() {
+        for door in &room.doors {
+            let (x, y) = door.at;
+            if rooms[index].map.tile(x, y) != Some(Tile::Door) {
+                return Err(format!(
+                    "room {:?}: ({x}, {y}) is not a door tile",
+                    room.name
+                ));
+            }
+            let Some(to) = world.rooms.iter().position(|r| r.name == door.to) else {
+                return Err(format!(
+                    "room {:?}: door at ({x}, {y}) leads to unknown room {:?}",
+                    room.name, door.to
+                ));
+            };
+            rooms[index].doors.insert(door.at, to);
+        }
+    }
+
+    if let Some(room) = rooms.iter().find(|room| !room.has_spawn_point()) {
+        return Err(format!("room {:?} has nowhere to stand", room.name));
+    }
+    Ok(rooms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORLD: &str = r#"
+        [[rooms]]
+        name = "lobby"
+        map = '''
+######
+#....+
+######
+'''
+        doors = [{ at = [5, 1], to = "garden" }]
+
+        [[rooms]]
+        name = "garden"
+        map = "+..."
+        doors = [{ at = [0, 0], to = "lobby" }]
+    "#;
+
+    /// A world of one room named `lobby`, with `room` added to its table.
+    fn lobby(room: &str) -> Result<Vec<Room>, String> {
+        parse_world(&format!("[[rooms]]\nname = \"lobby\"\n{room}"))
+    }
+
+    #[test]
+    fn doors_lead_between_rooms() {
+        let rooms = parse_world(WORLD).unwrap();
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(
+            (rooms[0].name.as_str(), rooms[1].name.as_str()),
+            ("lobby", "garden")
+        );
+        assert_eq!(rooms[0].door(4, 1), Some(1));
+        assert_eq!(rooms[0].door(2, 1), None);
+        assert_eq!(rooms[1].door(0, 0), Some(0));
+        // Nobody is placed where they would be sent straight on.
+        assert_eq!(rooms[0].spawn_point(), (2, 1));
+        assert_eq!(rooms[1].spawn_point(), (2, 0));
+    }
+
+    #[test]
+    fn unlinked_doors_are_floor() {
+        let rooms = lobby("map = \"+...\"").unwrap();
+        assert_eq!(rooms[0].door(0, 0), None);
+        assert_eq!(rooms[0].spawn_point(), (0, 0));
+    }
+
+    #[test]
+    fn broken_worlds_are_refused() {
+        assert_eq!(
+            parse_world("rooms = []").err().unwrap(),
+            "the world has no rooms"
+        );
+        assert!(
+            parse_world(&format!(
+                "{WORLD}\n[[rooms]]\nname = \"lobby\"\nmap = \"..\""
+            ))
+            .err()
+            .unwrap()
+            .contains("defined twice")
+        );
+        assert!(
+            lobby("map = \"..x.\"")
+                .err()
+                .unwrap()
+                .starts_with("room \"lobby\": unknown tile 'x'")
+        );
+        assert_eq!(
+            lobby("map = \"+...\"\ndoors = [{ at = [2, 0], to = \"lobby\" }]")
+                .err()
+                .unwrap(),
+            "room \"lobby\": (2, 0) is not a door tile"
+        );
+        assert_eq!(
+            lobby("map = \"+...\"\ndoors = [{ at = [0, 0], to = \"attic\" }]")
+                .err()
+                .unwrap(),
+            "room \"lobby\": door at (0, 0) leads to unknown room \"attic\""
+        );
+    }
+
+    #[test]
+    fn rooms_need_somewhere_to_stand() {
+        assert_eq!(
+            lobby("map = \"#..#\"").err().unwrap(),
+            "room \"lobby\" has nowhere to stand"
+        );
+        assert_eq!(
+            lobby("map = \"+.#\"\ndoors = [{ at = [0, 0], to = \"lobby\" }]")
+                .err()
+                .unwrap(),
+            "room \"lobby\" has nowhere to stand"
+        );
+    }
+}
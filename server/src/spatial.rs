@@ -0,0 +1,260 @@
+use std::{collections::HashMap, hash::Hash, ops::RangeInclusive};
+
+/// Height of a cell in rows, and width in squares.
+const CELL_SIZE: u16 = 8;
+
+/// Keeps track of where things are on a map, bucketed into cells a few
+/// squares across, so finding everything near a spot only looks at the cells
+/// around it instead of everything on the map.
+///
+/// Positions are map coordinates. Squares are two columns wide, so cells
+/// cover twice as many columns as rows and distances count columns half as
+/// much, the same as everywhere else players are measured.
+pub struct SpatialIndex<K> {
+    columns: u16,
+    rows: u16,
+    /// Each cell's entries with their positions, row by row.
+    cells: Vec<Vec<(K, u16, u16)>>,
+    positions: HashMap<K, (u16, u16)>,
+}
+
+impl<K: Copy + Eq + Hash> SpatialIndex<K> {
+    /// An empty index for a map of the given size.
+    pub fn new(width: u16, height: u16) -> Self {
+        let columns = (width / 2).div_ceil(CELL_SIZE).max(1);
+        let rows = height.div_ceil(CELL_SIZE).max(1);
+        Self {
+            columns,
+            rows,
+            cells: vec![Vec::new(); usize::from(columns) * usize::from(rows)],
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Puts `key` at `(x, y)`, moving it if it was already somewhere else.
+    pub fn insert(&mut self, key: K, x: u16, y: u16) {
+        self.remove(key);
+        let cell = self.cell(x, y);
+        self.cells[cell].push((key, x, y));
+        self.positions.insert(key, (x, y));
+    }
+
+    pub fn remove(&mut self, key: K) {
+        let Some((x, y)) = self.positions.remove(&key) else {
+            return;
+        };
+        let cell = self.cell(x, y);
+        let entries = &mut self.cells[cell];
+        if let Some(index) = entries.iter().position(|(k, ..)| *k == key) {
+            entries.swap_remove(index);
+        }
+    }
+
+    /// Everything within `radius` squares of `(x, y)`.
+    pub fn within(&self, x: u16, y: u16, radius: u16) -> impl Iterator<Item = K> + '_ {
+        let (column, row) = (x / 2, y);
+        let columns = span(column, radius, self.columns);
+        let rows = span(row, radius, self.rows);
+        self.entries(columns, rows)
+            .filter(move |&(_, px, py)| in_range((x, y), (px, py), radius))
+            .map(|(key, ..)| key)
+    }
+
+    /// Everything whose square would overlap one standing at `(x, y)`.
+    pub fn overlapping(&self, x: u16, y: u16) -> impl Iterator<Item = K> + '_ {
+        let last = (x.saturating_add(1) / 2 / CELL_SIZE).min(self.columns - 1);
+        let first = (x.saturating_sub(1) / 2 / CELL_SIZE).min(last);
+        let row = (y / CELL_SIZE).min(self.rows - 1);
+        self.entries(first..=last, row..=row)
+            .filter(move |&(_, px, py)| py == y && px.abs_diff(x) < 2)
+            .map(|(key, ..)| key)
+    }
+
+    fn entries(
+        &self,
+        columns: RangeInclusive<u16>,
+        rows: RangeInclusive<u16>,
+    ) -> impl Iterator<Item = (K, u16, u16)> + '_ {
+        rows.flat_map(move |row| columns.clone().map(move |column| (column, row)))
+            .flat_map(|(column, row)| {
+                &self.cells[usize::from(row) * usize::from(self.columns) + usize::from(column)]
+            })
+            .copied()
+    }
+
+    /// Index into `cells` of the cell holding `(x, y)`. Positions past the
+    /// edge of the map go in the outermost cells.
+    fn cell(&self, x: u16, y: u16) -> usize {
+        let column = (x / 2 / CELL_SIZE).min(self.columns - 1);
+        let row = (y / CELL_SIZE).min(self.rows - 1);
+        usize::from(row) * usize::from(self.columns) + usize::from(column)
+    }
+}
+
+/// The cells, along one axis with `cells` of them, within `radius` of `at`.
+fn span(at: u16, radius: u16, cells: u16) -> RangeInclusive<u16> {
+    let first = at.saturating_sub(radius) / CELL_SIZE;
+    let last = (at.saturating_add(radius) / CELL_SIZE).min(cells - 1);
+    first.min(last)..=last
+}
+
+/// Whether `b` is within `radius` squares of `a`.
+fn in_range(a: (u16, u16), b: (u16, u16), radius: u16) -> bool {
+    let dx = u64::from((a.0 / 2).abs_diff(b.0 / 2));
+    let dy = u64::from(a.1.abs_diff(b.1));
+    let radius = u64::from(radius);
+    dx * dx + dy * dy <= radius * radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u16 = 50;
+    const HEIGHT: u16 = 30;
+
+    /// Positions spread over the map and a little past its edges, from a
+    /// fixed xorshift sequence so failures reproduce.
+    fn positions(count: usize) -> Vec<(u16, u16)> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move |bound: u16| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % u64::from(bound)) as u16
+        };
+        (0..count)
+            .map(|_| (next(WIDTH + 8), next(HEIGHT + 8)))
+            .collect()
+    }
+
+    /// Spots to query from: corners, edges, just past them and far past
+    /// them, besides the usual positions.
+    fn probes() -> Vec<(u16, u16)> {
+        let mut probes = vec![
+            (0, 0),
+            (WIDTH - 1, 0),
+            (0, HEIGHT - 1),
+            (WIDTH - 1, HEIGHT - 1),
+            (WIDTH, HEIGHT),
+            (WIDTH + 5, 3),
+            (7, HEIGHT + 5),
+            (u16::MAX, u16::MAX),
+        ];
+        probes.extend(positions(40));
+        probes
+    }
+
+    fn index_of(positions: &[(u16, u16)]) -> SpatialIndex<usize> {
+        let mut index = SpatialIndex::new(WIDTH, HEIGHT);
+        for (key, &(x, y)) in positions.iter().enumerate() {
+            index.insert(key, x, y);
+        }
+        index
+    }
+
+    fn sorted(keys: impl Iterator<Item = usize>) -> Vec<usize> {
+        let mut keys: Vec<usize> = keys.collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    fn scan_within(positions: &[(u16, u16)], x: u16, y: u16, radius: u16) -> Vec<usize> {
+        sorted(
+            positions
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| in_range((x, y), **p, radius))
+                .map(|(key, _)| key),
+        )
+    }
+
+    fn scan_overlapping(positions: &[(u16, u16)], x: u16, y: u16) -> Vec<usize> {
+        sorted(
+            positions
+                .iter()
+                .enumerate()
+                .filter(|&(_, &(px, py))| py == y && px.abs_diff(x) < 2)
+                .map(|(key, _)| key),
+        )
+    }
+
+    fn assert_matches_scan(index: &SpatialIndex<usize>, positions: &[(u16, u16)]) {
+        assert_eq!(index.len(), positions.len());
+        for (x, y) in probes() {
+            for radius in [0, 1, 3, CELL_SIZE, 20, u16::MAX] {
+                assert_eq!(
+                    sorted(index.within(x, y, radius)),
+                    scan_within(positions, x, y, radius),
+                    "within {radius} of ({x}, {y})"
+                );
+            }
+            assert_eq!(
+                sorted(index.overlapping(x, y)),
+                scan_overlapping(positions, x, y),
+                "overlapping ({x}, {y})"
+            );
+        }
+    }
+
+    #[test]
+    fn queries_match_a_scan() {
+        let positions = positions(300);
+        assert_matches_scan(&index_of(&positions), &positions);
+    }
+
+    #[test]
+    fn queries_match_a_scan_after_moves_between_cells() {
+        let mut positions = positions(300);
+        let mut index = index_of(&positions);
+        for (key, (x, y)) in positions.iter_mut().enumerate() {
+            // Crosses a cell boundary in one or both directions, and now and
+            // then off the map.
+            *x = x.wrapping_add(CELL_SIZE * 2 + 1) % (WIDTH + 8);
+            if key % 2 == 0 {
+                *y = (*y + CELL_SIZE) % (HEIGHT + 8);
+            }
+            index.insert(key, *x, *y);
+        }
+        assert_matches_scan(&index, &positions);
+    }
+
+    #[test]
+    fn removed_entries_are_not_found() {
+        let positions = positions(100);
+        let mut index = index_of(&positions);
+        for key in (0..positions.len()).step_by(3) {
+            index.remove(key);
+        }
+        let kept: Vec<usize> = (0..positions.len()).filter(|key| key % 3 != 0).collect();
+        assert_eq!(index.len(), kept.len());
+        for (x, y) in probes() {
+            let expected: Vec<usize> = scan_within(&positions, x, y, 10)
+                .into_iter()
+                .filter(|key| key % 3 != 0)
+                .collect();
+            assert_eq!(sorted(index.within(x, y, 10)), expected);
+        }
+    }
+
+    #[test]
+    fn edge_cells_hold_everything_past_the_map() {
+        let mut index = SpatialIndex::new(WIDTH, HEIGHT);
+        index.insert(1, u16::MAX, u16::MAX);
+        index.insert(2, WIDTH + 1, 0);
+        assert_eq!(sorted(index.overlapping(u16::MAX, u16::MAX)), vec![1]);
+        assert_eq!(sorted(index.overlapping(WIDTH, 0)), vec![2]);
+        assert_eq!(
+            sorted(index.within(WIDTH - 1, HEIGHT - 1, 0)),
+            Vec::<usize>::new()
+        );
+    }
+}
@@ -0,0 +1,648 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::debug;
+use roam_protocol::{
+    ChatMessage, ChatRequest, ChatScope, ClientMessage, Connect, DecodeError, Direction, Emote,
+    Encoding, Input, MAX_NAME_LENGTH, MapChunk, PROTOCOL_VERSION, Player, PlayerColor, PlayerEmote,
+    PlayerId, PlayerSet, Presence, ServerMessage, Welcome, is_valid_glyph, validate_chat,
+    validate_name,
+};
+
+use crate::connection::{Connection, Departed};
+use crate::room::Room;
+
+/// `CONNECT`s per second, across all addresses, we tell why we turned them
+/// away. The sender has not shown it is at the address yet, so this caps what
+/// a forged one can have us send someone else.
+const REFUSALS_PER_SECOND: u32 = 10;
+
+/// How the world plays, fixed when the server starts.
+pub struct Settings {
+    /// Whether players block each other outside social zones.
+    pub player_collisions: bool,
+    /// How far, in squares, players can hear nearby chat.
+    pub say_radius: u16,
+    /// How far, in squares, players are sent each other's whereabouts.
+    pub view_radius: u16,
+    /// Ticks per second.
+    pub tick_rate: u32,
+    /// Seconds a connection survives without hearing from its client.
+    pub player_lifetime: u32,
+    /// Seconds a timed-out player can still be resumed with its token.
+    pub resume_window: u32,
+    /// Most players connected at once, if there is a limit.
+    pub max_players: Option<usize>,
+}
+
+/// Everything the server keeps track of between datagrams. It is owned by
+/// the thread running the server and never touches the socket: replies pile
+/// up until taken with [`World::take_outgoing`].
+pub struct World {
+    settings: Settings,
+    /// Ticks run since the server started.
+    tick: u32,
+    /// Every room in the world, each with its own players. New players
+    /// start in the first.
+    rooms: Vec<Room>,
+    connections: HashMap<SocketAddr, Connection>,
+    next_player_id: u32,
+    /// Players whose connection timed out, by resume token.
+    departed: HashMap<u64, Departed>,
+    /// Refusals left to send in the current second.
+    refusals_left: u32,
+    outbox: Outbox,
+}
+
+/// Datagrams waiting to be sent, with where they go.
+#[derive(Default)]
+struct Outbox(Vec<(SocketAddr, Vec<u8>)>);
+
+impl Outbox {
+    fn send(&mut self, addr: SocketAddr, message: &ServerMessage, encoding: Encoding) {
+        self.0.push((addr, message.encode(encoding)));
+    }
+}
+
+impl World {
+    pub fn new(rooms: Vec<Room>, settings: Settings) -> Self {
+        Self {
+            settings,
+            tick: 0,
+            rooms,
+            connections: HashMap::new(),
+            next_player_id: 1,
+            departed: HashMap::new(),
+            refusals_left: REFUSALS_PER_SECOND,
+            outbox: Outbox::default(),
+        }
+    }
+
+    /// Datagrams queued since the last call, ready for the socket.
+    pub fn take_outgoing(&mut self) -> Vec<(SocketAddr, Vec<u8>)> {
+        std::mem::take(&mut self.outbox.0)
+    }
+
+    /// Acts on a message from the client at `addr`. Until the address is
+    /// confirmed it may not be the sender's, so only the handshake is acted
+    /// on and nothing else is answered.
+    pub fn handle(&mut self, addr: SocketAddr, message: ClientMessage) {
+        let confirmed = self
+            .connections
+            .get(&addr)
+            .is_some_and(Connection::is_confirmed);
+        match message {
+            ClientMessage::Connect(connect) => self.connect(addr, connect),
+            ClientMessage::Confirm(token) => self.confirm(addr, token),
+            ClientMessage::Disconnect => self.disconnect(addr),
+            _ if !confirmed => {}
+            ClientMessage::Move(input) => self.move_player(addr, input),
+            ClientMessage::Ack(sequence) => {
+                if let Some(connection) = self.connections.get_mut(&addr) {
+                    connection.refresh();
+                    connection.acknowledge(sequence);
+                }
+            }
+            ClientMessage::Heartbeat => {
+                if let Some(connection) = self.connections.get_mut(&addr) {
+                    connection.refresh();
+                    let encoding = connection.encoding;
+                    self.outbox.send(addr, &ServerMessage::Heartbeat, encoding);
+                }
+            }
+            ClientMessage::Chat(request) => self.chat(addr, request),
+            ClientMessage::Emote(emote) => self.emote(addr, emote),
+            ClientMessage::SetPresence(presence) => {
+                if let Some(connection) = self.connections.get_mut(&addr) {
+                    connection.refresh();
+                    // Everyone else finds out with the next snapshot.
+                    self.rooms[connection.room].set_presence(addr, presence);
+                }
+            }
+        }
+    }
+
+    /// Tells the client at `addr` that its datagram could not be read. Only
+    /// confirmed clients are told: the sender's address could be forged to
+    /// aim our replies at someone, and a few replies a second are plenty for
+    /// a real client.
+    pub fn reject(&mut self, addr: SocketAddr, error: DecodeError) {
+        let Some(connection) = self
+            .connections
+            .get_mut(&addr)
+            .filter(|connection| connection.is_confirmed())
+        else {
+            return;
+        };
+        if connection.take_error() {
+            let reply = ServerMessage::Error(error.to_string());
+            self.outbox.send(addr, &reply, connection.encoding);
+        }
+    }
+
+    /// Advances the world by one tick. Timers kept in seconds move on once
+    /// every `tick_rate` ticks.
+    pub fn step(&mut self) {
+        self.tick = self.tick.wrapping_add(1);
+        if self.tick.is_multiple_of(self.settings.tick_rate) {
+            self.every_second();
+        }
+    }
+
+    /// Ages connections and departed players, and hands out fresh budgets.
+    fn every_second(&mut self) {
+        // Until a client acknowledges a snapshot it may not have the WELCOME
+        // for the room it is in. It gets a few more, and after that we drop
+        // it, as a real client reconnects once the snapshots stop. An address
+        // that never confirmed gets nothing more: its client, if it has one,
+        // connects again by itself, and a spoofed one costs us no more than
+        // the first WELCOME.
+        for (addr, connection) in self.connections.iter_mut() {
+            if connection.is_welcomed() {
+                continue;
+            }
+            if !connection.take_welcome_resend() {
+                connection.lifetime = 0;
+            } else if connection.is_confirmed() {
+                welcome(
+                    &mut self.outbox,
+                    *addr,
+                    connection,
+                    &self.rooms,
+                    self.settings.tick_rate,
+                );
+            }
+        }
+        for connection in self.connections.values_mut() {
+            if connection.lifetime > 0 {
+                connection.lifetime -= 1;
+            }
+            connection.refill_budgets();
+        }
+        self.refusals_left = REFUSALS_PER_SECOND;
+        // Timed-out players are kept aside for a while so their client can
+        // resume them after reconnecting, as long as it ever got to play.
+        for (addr, connection) in self.connections.extract_if(|_, c| c.lifetime == 0) {
+            if let Some(player) = self.rooms[connection.room].remove(addr)
+                && connection.is_confirmed()
+            {
+                self.departed.insert(
+                    connection.resume_token,
+                    Departed::new(player, connection.room, self.settings.resume_window),
+                );
+            }
+        }
+        self.departed.retain(|_, departed| {
+            departed.lifetime = departed.lifetime.saturating_sub(1);
+            departed.lifetime > 0
+        });
+        debug!("Active connections: {:?}", self.connections);
+        for room in &self.rooms {
+            let players: Vec<&Player> = room.players().collect();
+            debug!("Players in {}: {:?}", room.name, players);
+        }
+    }
+
+    /// Sends every confirmed client a snapshot of the players it can see,
+    /// stamped with the current tick.
+    pub fn broadcast(&mut self) {
+        for (addr, connection) in self.connections.iter_mut() {
+            if !connection.is_confirmed() {
+                continue;
+            }
+            let room = &self.rooms[connection.room];
+            let Some(own) = room.player(*addr) else {
+                continue;
+            };
+            // Clients learn their own position from snapshots too, since only
+            // the server moves players.
+            let visible: PlayerSet = room
+                .near(own.x, own.y, self.settings.view_radius)
+                .map(|(_, p)| (p.id, p.clone()))
+                .collect();
+            // Players coming into view are in the snapshot, and those going
+            // out of it in its removals.
+            connection.set_view(&visible);
+            let Some(snapshot) = connection.snapshot(self.tick, visible) else {
+                continue;
+            };
+            let message = ServerMessage::Snapshot(snapshot);
+            self.outbox.send(*addr, &message, connection.encoding);
+        }
+    }
+
+    fn connect(&mut self, addr: SocketAddr, connect: Connect) {
+        if connect.version != PROTOCOL_VERSION {
+            self.refuse(
+                addr,
+                format!(
+                    "unsupported protocol version {}, server speaks {}",
+                    connect.version, PROTOCOL_VERSION
+                ),
+            );
+            return;
+        }
+        if !connect.name.is_empty()
+            && let Err(e) = validate_name(&connect.name)
+        {
+            self.refuse(addr, format!("invalid name: {e}"));
+            return;
+        }
+        if let Some(glyph) = connect.glyph
+            && !is_valid_glyph(glyph)
+        {
+            self.refuse(
+                addr,
+                format!("invalid glyph {glyph:?}, use a printable ASCII character"),
+            );
+            return;
+        }
+
+        // Taking over a live session, or reconnecting from the same
+        // address, does not add a player. Nor do connections that have not
+        // confirmed their address yet, so spoofed CONNECTs cannot fill the
+        // server.
+        let joining = !self.connections.contains_key(&addr)
+            && !connect.resume.is_some_and(|token| {
+                self.connections
+                    .values()
+                    .any(|connection| connection.resume_token == token)
+            });
+        let players = self
+            .connections
+            .values()
+            .filter(|connection| connection.is_confirmed())
+            .count();
+        if joining && self.settings.max_players.is_some_and(|max| players >= max) {
+            self.refuse(addr, "server is full, try again later".to_string());
+            return;
+        }
+
+        let resumed = connect.resume.and_then(|token| {
+            // The session may still be live under another address, e.g. when
+            // the client's NAT mapping changed.
+            let live = self
+                .connections
+                .iter()
+                .find(|(_, connection)| connection.resume_token == token)
+                .map(|(addr, _)| *addr);
+            match live {
+                Some(old_addr) => {
+                    let room = self.connections.remove(&old_addr)?.room;
+                    let player = self.rooms[room].remove(old_addr)?;
+                    Some((token, player, room, true))
+                }
+                None => self
+                    .departed
+                    .remove(&token)
+                    .map(|departed| (token, departed.player, departed.room, true)),
+            }
+        });
+        // Resuming another session from an address that already has one
+        // leaves the address's own player behind, so it goes.
+        if resumed.is_some() {
+            self.disconnect(addr);
+        }
+
+        let connections = &mut self.connections;
+        let rooms = &mut self.rooms;
+        // A repeated CONNECT from the same address keeps its player. Resumed
+        // players keep their name and room too, and having held the token
+        // already shows who they are.
+        let existing = resumed.or_else(|| {
+            let connection = connections.remove(&addr)?;
+            let player = rooms[connection.room].remove(addr)?;
+            let confirmed = connection.is_confirmed();
+            Some((connection.resume_token, player, connection.room, confirmed))
+        });
+        let (resume_token, player, room, confirmed) = existing.unwrap_or_else(|| {
+            let id = PlayerId(self.next_player_id);
+            self.next_player_id += 1;
+            let (x, y) = rooms[0].spawn_point();
+            let requested = match connect.name.as_str() {
+                "" => format!("player{}", id.0),
+                name => name.to_string(),
+            };
+            // Names are unique across rooms. Departed players may still come
+            // back, so their names stay taken.
+            let taken = rooms
+                .iter()
+                .flat_map(|room| room.players())
+                .chain(self.departed.values().map(|departed| &departed.player))
+                .map(|player| player.name.as_str());
+            let name = unique_name(&requested, taken);
+            let color = connect
+                .color
+                .unwrap_or_else(|| least_used_color(rooms[0].players()));
+            let player = Player {
+                id,
+                name,
+                color,
+                glyph: connect.glyph,
+                presence: Presence::default(),
+                x,
+                y,
+            };
+            (new_resume_token(), player, 0, false)
+        });
+
+        rooms[room].insert(addr, player);
+        let connection = connections
+            .entry(addr)
+            .insert_entry(Connection::new(
+                connect.encoding,
+                resume_token,
+                room,
+                self.settings.player_lifetime,
+            ))
+            .into_mut();
+        if confirmed {
+            connection.confirm();
+        }
+        welcome(
+            &mut self.outbox,
+            addr,
+            connection,
+            rooms,
+            self.settings.tick_rate,
+        );
+    }
+
+    /// Tells `addr` why its `CONNECT` was turned away, in the encoding every
+    /// client understands, unless this second's refusals have run out.
+    fn refuse(&mut self, addr: SocketAddr, reason: String) {
+        if self.refusals_left == 0 {
+            return;
+        }
+        self.refusals_left -= 1;
+        self.outbox
+            .send(addr, &ServerMessage::Error(reason), Encoding::Json);
+    }
+
+    /// Starts sending the client at `addr` its room's map and snapshots, now
+    /// that it has echoed the token from its `WELCOME` and so shown it really
+    /// is at that address.
+    fn confirm(&mut self, addr: SocketAddr, token: u64) {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        if connection.resume_token != token {
+            return;
+        }
+        connection.refresh();
+        if !connection.is_confirmed() {
+            connection.confirm();
+            send_map(&mut self.outbox, addr, connection, &self.rooms);
+        }
+    }
+
+    fn disconnect(&mut self, addr: SocketAddr) {
+        let Some(left) = self.connections.remove(&addr) else {
+            return;
+        };
+        let Some(player) = self.rooms[left.room].remove(addr) else {
+            return;
+        };
+        let message = ServerMessage::PlayerLeft(player.id);
+        // Only those who could see the player need telling.
+        for (addr, connection) in self.connections.iter_mut() {
+            if connection.forget(player.id) {
+                self.outbox.send(*addr, &message, connection.encoding);
+            }
+        }
+    }
+
+    /// Moves a player one step on behalf of its client. Clients only send
+    /// intents; where the player ends up is decided here.
+    fn move_player(&mut self, addr: SocketAddr, input: Input) {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        connection.refresh();
+        // Late or duplicated datagrams must not move the player twice.
+        if input.sequence <= connection.last_input {
+            return;
+        }
+        // Rejected inputs still count as processed, so the client stops
+        // predicting them and snaps back to where we say it is.
+        connection.last_input = input.sequence;
+        let room = &mut self.rooms[connection.room];
+        let collisions = self.settings.player_collisions;
+        if !(connection.take_move() && try_move(room, addr, input.direction, collisions)) {
+            let reply = ServerMessage::MoveRejected(input.sequence);
+            self.outbox.send(addr, &reply, connection.encoding);
+            return;
+        }
+        // Stepping onto a door takes the player through to where it leads.
+        let through = room
+            .player(addr)
+            .and_then(|player| room.door(player.x, player.y));
+        if let Some(to) = through {
+            self.enter_room(addr, to);
+        }
+    }
+
+    /// Moves the player at `addr` to the spawn point of room `to` and
+    /// welcomes its client there.
+    fn enter_room(&mut self, addr: SocketAddr, to: usize) {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        let Some(mut player) = self.rooms[connection.room].remove(addr) else {
+            return;
+        };
+        (player.x, player.y) = self.rooms[to].spawn_point();
+        self.rooms[to].insert(addr, player);
+        connection.enter(to);
+        welcome(
+            &mut self.outbox,
+            addr,
+            connection,
+            &self.rooms,
+            self.settings.tick_rate,
+        );
+    }
+
+    /// Relays a chat message to everyone in the speaker's room it is meant
+    /// for, stamped with the sender's name and the time. Nearby chat only
+    /// reaches players within the say radius of the speaker.
+    fn chat(&mut self, addr: SocketAddr, request: ChatRequest) {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        connection.refresh();
+        let refused = match validate_chat(&request.text) {
+            Err(e) => Some(format!("invalid chat message: {e}")),
+            Ok(()) if !connection.take_chat() => Some("chatting too fast".to_string()),
+            Ok(()) => None,
+        };
+        if let Some(reason) = refused {
+            let reply = ServerMessage::Error(reason);
+            self.outbox.send(addr, &reply, connection.encoding);
+            return;
+        }
+        let index = connection.room;
+        let room = &self.rooms[index];
+        let Some(speaker) = room.player(addr) else {
+            return;
+        };
+        let sent_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let message = ServerMessage::Chat(ChatMessage {
+            from: speaker.id,
+            name: speaker.name.clone(),
+            text: request.text,
+            scope: request.scope,
+            sent_at,
+        });
+        let listeners: Vec<SocketAddr> = match request.scope {
+            ChatScope::Everyone => self
+                .connections
+                .iter()
+                .filter(|(_, connection)| connection.room == index)
+                .map(|(addr, _)| *addr)
+                .collect(),
+            ChatScope::Nearby => room
+                .near(speaker.x, speaker.y, self.settings.say_radius)
+                .map(|(addr, _)| addr)
+                .collect(),
+        };
+        for addr in listeners {
+            if let Some(connection) = self.connections.get(&addr) {
+                self.outbox.send(addr, &message, connection.encoding);
+            }
+        }
+    }
+
+    /// Relays an emote to everyone who can see the player. Emotes come out
+    /// of the same budget as chat.
+    fn emote(&mut self, addr: SocketAddr, emote: Emote) {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        connection.refresh();
+        if !connection.take_chat() {
+            let reply = ServerMessage::Error("emoting too fast".to_string());
+            self.outbox.send(addr, &reply, connection.encoding);
+            return;
+        }
+        let room = connection.room;
+        let Some(player) = self.rooms[room].player(addr).map(|p| p.id) else {
+            return;
+        };
+        let message = ServerMessage::Emote(PlayerEmote { player, emote });
+        // Our own client sees us even before the first broadcast.
+        let audience = self
+            .connections
+            .iter()
+            .filter(|(other, c)| **other == addr || (c.room == room && c.can_see(player)));
+        for (addr, connection) in audience {
+            self.outbox.send(*addr, &message, connection.encoding);
+        }
+    }
+}
+
+/// Tells the client at `addr` about its player and the room it is in, and
+/// sends it the room's map if it has confirmed its address. The client
+/// starts its snapshots over on receiving this, so we do too.
+fn welcome(
+    outbox: &mut Outbox,
+    addr: SocketAddr,
+    connection: &mut Connection,
+    rooms: &[Room],
+    tick_rate: u32,
+) {
+    let room = &rooms[connection.room];
+    let Some(player) = room.player(addr) else {
+        return;
+    };
+    connection.restart();
+    let welcome = ServerMessage::Welcome(Welcome {
+        player: player.clone(),
+        room: connection.room as u16,
+        room_name: room.name.clone(),
+        map_width: room.map.width(),
+        map_height: room.map.height(),
+        tick_rate,
+        encoding: connection.encoding,
+        resume_token: connection.resume_token,
+    });
+    outbox.send(addr, &welcome, connection.encoding);
+    if connection.is_confirmed() {
+        send_map(outbox, addr, connection, rooms);
+    }
+}
+
+/// Sends the client at `addr` the map of the room it is in, a few rows at a
+/// time.
+fn send_map(outbox: &mut Outbox, addr: SocketAddr, connection: &Connection, rooms: &[Room]) {
+    for (row, tiles) in rooms[connection.room].map.chunks() {
+        let chunk = ServerMessage::MapChunk(MapChunk {
+            room: connection.room as u16,
+            row,
+            tiles,
+        });
+        outbox.send(addr, &chunk, connection.encoding);
+    }
+}
+
+/// Moves the player at `addr` one step within `room` unless a wall or,
+/// with `collisions` on and outside social zones, another player is in the
+/// way.
+fn try_move(room: &mut Room, addr: SocketAddr, direction: Direction, collisions: bool) -> bool {
+    let Some(player) = room.player(addr) else {
+        return false;
+    };
+    let Some((x, y)) = direction.step(player, &room.map) else {
+        return false;
+    };
+    if collisions && !room.map.is_social(x, y) && room.overlapping(x, y).any(|other| other != addr)
+    {
+        return false;
+    }
+    room.move_to(addr, x, y);
+    true
+}
+
+/// `name`, or the first of `name2`, `name3`, ... nobody else uses, shortened
+/// if needed to stay within [`MAX_NAME_LENGTH`].
+fn unique_name<'a>(name: &str, taken: impl Iterator<Item = &'a str>) -> String {
+    let taken: Vec<&str> = taken.collect();
+    if !taken.contains(&name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| {
+            let suffix = n.to_string();
+            let keep = name.len().min(MAX_NAME_LENGTH - suffix.len());
+            format!("{}{suffix}", &name[..keep])
+        })
+        .find(|candidate| !taken.contains(&candidate.as_str()))
+        .expect("some suffix is free")
+}
+
+/// The palette colour the fewest `players` have, so squares stay easy to tell
+/// apart. Ties go to whichever comes first in the palette.
+fn least_used_color<'a>(players: impl Iterator<Item = &'a Player>) -> PlayerColor {
+    let mut counts = [0usize; PlayerColor::ALL.len()];
+    for player in players {
+        if let Some(index) = PlayerColor::ALL.iter().position(|&c| c == player.color) {
+            counts[index] += 1;
+        }
+    }
+    let index = (0..counts.len())
+        .min_by_key(|&index| counts[index])
+        .unwrap_or(0);
+    PlayerColor::ALL[index]
+}
+
+/// Resume tokens only need to be hard to guess, not cryptographically strong;
+/// every `RandomState` is seeded with fresh random keys.
+fn new_resume_token() -> u64 {
+    RandomState::new().hash_one(SystemTime::now())
+}